@@ -9,7 +9,7 @@ export CONSUMER_TASK_ENABLE=true
 export FEEDS=$(pwd)/config.yml
 
 touch development.db
-sqlx migrate run --database-url sqlite://development.db
+sqlx migrate run --source migrations/sqlite --database-url sqlite://development.db
 
 RUST_BACKTRACE=1 RUST_LOG=debug RUST_LIB_BACKTRACE=1 cargo run --bin supercell
 
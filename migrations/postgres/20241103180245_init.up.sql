@@ -0,0 +1,26 @@
+-- Add up migration script here
+
+CREATE TABLE feed_content (
+  feed_id TEXT NOT NULL,
+  uri TEXT NOT NULL,
+  indexed_at BIGINT NOT NULL,
+  cid TEXT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT (now()),
+  PRIMARY KEY (feed_id, uri)
+);
+
+CREATE INDEX feed_content_idx_feed ON feed_content(feed_id, indexed_at DESC, cid DESC);
+
+CREATE TABLE consumer_control (
+  source TEXT NOT NULL,
+  time_us BIGINT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT (now()),
+  PRIMARY KEY (source)
+);
+
+CREATE TABLE verification_method_cache (
+  did TEXT NOT NULL,
+  multikey TEXT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT (now()),
+  PRIMARY KEY (did)
+);
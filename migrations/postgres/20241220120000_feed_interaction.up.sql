@@ -0,0 +1,11 @@
+-- Add up migration script here
+
+CREATE TABLE feed_interaction (
+  viewer TEXT NOT NULL,
+  uri TEXT NOT NULL,
+  event TEXT NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT (now()),
+  PRIMARY KEY (viewer, uri, event)
+);
+
+CREATE INDEX feed_interaction_idx_updated ON feed_interaction(updated_at);
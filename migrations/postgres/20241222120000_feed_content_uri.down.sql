@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+DROP INDEX feed_content_idx_uri;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+DROP TABLE feed_content;
+DROP TABLE consumer_control;
+DROP TABLE verification_method_cache;
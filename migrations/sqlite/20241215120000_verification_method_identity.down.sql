@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+ALTER TABLE verification_method_cache DROP COLUMN handle;
+ALTER TABLE verification_method_cache DROP COLUMN pds;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+
+ALTER TABLE verification_method_cache ADD COLUMN pds TEXT;
+ALTER TABLE verification_method_cache ADD COLUMN handle TEXT;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+
+CREATE INDEX feed_content_idx_uri ON feed_content(uri);
@@ -0,0 +1,204 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+
+type ArchiveEncoder = zstd::stream::write::Encoder<'static, BufWriter<File>>;
+
+/// Writes decompressed jetstream events to hourly, zstd compressed JSONL
+/// files named `jetstream-YYYYMMDDHH-YYYYMMDDHHMMSS.jsonl.zst`, where the
+/// second timestamp is when the writer was created.
+pub struct ArchiveWriter {
+    directory: PathBuf,
+    writer_id: String,
+    current: Option<(String, ArchiveEncoder)>,
+}
+
+impl ArchiveWriter {
+    pub fn new(directory: &str) -> Result<Self> {
+        Self::with_writer_id(directory, &Utc::now().format("%Y%m%d%H%M%S").to_string())
+    }
+
+    fn with_writer_id(directory: &str, writer_id: &str) -> Result<Self> {
+        std::fs::create_dir_all(directory).context("cannot create archive directory")?;
+        Ok(Self {
+            directory: PathBuf::from(directory),
+            writer_id: writer_id.to_string(),
+            current: None,
+        })
+    }
+
+    pub fn write(&mut self, event: &[u8]) -> Result<()> {
+        let key = Utc::now().format("%Y%m%d%H").to_string();
+        self.write_keyed(&key, event)
+    }
+
+    fn write_keyed(&mut self, key: &str, event: &[u8]) -> Result<()> {
+        let rotate = match &self.current {
+            Some((current_key, _)) => current_key != key,
+            None => true,
+        };
+        if rotate {
+            self.finish()?;
+
+            let path = self
+                .directory
+                .join(format!("jetstream-{}-{}.jsonl.zst", key, self.writer_id));
+            tracing::debug!(path = ?path, "opening archive");
+
+            // Each writer has its own files, so a frame left unfinished by a
+            // crash is never followed by another. Reopening an hour this writer
+            // already finished appends a new frame, which readers decode as a
+            // continuation of the same stream.
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("cannot open archive {}", path.display()))?;
+            let encoder = zstd::stream::write::Encoder::new(BufWriter::new(file), 0)
+                .context("cannot create archive encoder")?;
+            self.current = Some((key.to_string(), encoder));
+        }
+
+        let (_, encoder) = self.current.as_mut().expect("archive is open");
+        encoder.write_all(event).context("cannot write archive")?;
+        encoder.write_all(b"\n").context("cannot write archive")
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some((_, encoder)) = self.current.as_mut() {
+            encoder.flush().context("cannot flush archive")?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some((_, encoder)) = self.current.take() {
+            encoder
+                .finish()
+                .context("cannot finish archive")?
+                .flush()
+                .context("cannot flush archive")?;
+        }
+        Ok(())
+    }
+}
+
+/// Opens an archive for reading. Files ending in `.zst` are decompressed and
+/// all other files are read as plain JSONL.
+pub fn open_archive(path: &str) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path).with_context(|| format!("cannot open archive {}", path))?;
+
+    if Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension == "zst")
+    {
+        let decoder =
+            zstd::stream::read::Decoder::new(file).context("cannot create archive decoder")?;
+        Ok(Box::new(BufReader::new(decoder)))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_archive() {
+        let directory =
+            std::env::temp_dir().join(format!("supercell-archive-{}", std::process::id()));
+        let directory = directory.to_str().expect("path is valid").to_string();
+
+        let mut archive = ArchiveWriter::with_writer_id(&directory, "20241101190000")
+            .expect("archive is created");
+        archive
+            .write_keyed("2024110119", br#"{"one":1}"#)
+            .expect("event is written");
+        archive.flush().expect("archive is flushed");
+        archive
+            .write_keyed("2024110119", br#"{"two":2}"#)
+            .expect("event is written");
+        archive
+            .write_keyed("2024110120", br#"{"three":3}"#)
+            .expect("event is written");
+        archive.finish().expect("archive is finished");
+
+        // Reopening an existing hour appends a new frame to the same file.
+        archive
+            .write_keyed("2024110119", br#"{"four":4}"#)
+            .expect("event is written");
+        archive.finish().expect("archive is finished");
+
+        let read_lines = |name: &str| {
+            let path = format!("{}/{}", directory, name);
+            open_archive(&path)
+                .expect("archive is readable")
+                .lines()
+                .collect::<std::io::Result<Vec<String>>>()
+                .expect("lines are readable")
+        };
+
+        assert_eq!(
+            read_lines("jetstream-2024110119-20241101190000.jsonl.zst"),
+            vec![r#"{"one":1}"#, r#"{"two":2}"#, r#"{"four":4}"#]
+        );
+        assert_eq!(
+            read_lines("jetstream-2024110120-20241101190000.jsonl.zst"),
+            vec![r#"{"three":3}"#]
+        );
+
+        std::fs::remove_dir_all(&directory).expect("directory is removed");
+    }
+
+    #[test]
+    fn restart_after_crash() {
+        let directory =
+            std::env::temp_dir().join(format!("supercell-archive-crash-{}", std::process::id()));
+        let directory = directory.to_str().expect("path is valid").to_string();
+
+        // A crash leaves the frame unfinished after the last flush.
+        let mut crashed = ArchiveWriter::with_writer_id(&directory, "20241101190000")
+            .expect("archive is created");
+        crashed
+            .write_keyed("2024110119", br#"{"one":1}"#)
+            .expect("event is written");
+        crashed.flush().expect("archive is flushed");
+        crashed
+            .write_keyed("2024110119", br#"{"lost":0}"#)
+            .expect("event is written");
+        drop(crashed);
+
+        let mut restarted = ArchiveWriter::with_writer_id(&directory, "20241101193000")
+            .expect("archive is created");
+        restarted
+            .write_keyed("2024110119", br#"{"two":2}"#)
+            .expect("event is written");
+        restarted.finish().expect("archive is finished");
+
+        let read_lines = |name: &str| {
+            let path = format!("{}/{}", directory, name);
+            open_archive(&path)
+                .expect("archive is readable")
+                .lines()
+                .map_while(std::io::Result::ok)
+                .collect::<Vec<String>>()
+        };
+
+        // Events flushed before the crash are readable and events archived
+        // after the restart are not lost behind the unfinished frame.
+        assert_eq!(
+            read_lines("jetstream-2024110119-20241101190000.jsonl.zst"),
+            vec![r#"{"one":1}"#]
+        );
+        assert_eq!(
+            read_lines("jetstream-2024110119-20241101193000.jsonl.zst"),
+            vec![r#"{"two":2}"#]
+        );
+
+        std::fs::remove_dir_all(&directory).expect("directory is removed");
+    }
+}
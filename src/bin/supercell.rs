@@ -1,18 +1,22 @@
-use anyhow::Result;
-use sqlx::SqlitePool;
-use std::collections::HashMap;
-use std::collections::HashSet;
+use anyhow::{anyhow, Result};
 use std::env;
-use supercell::vmc::VerificationMethodCacheTask;
+use std::sync::Arc;
+use supercell::prune::FeedContentPruneTask;
+use supercell::publish::{publish_feeds, PublishAccount};
+use supercell::reload::FeedsReloadTask;
+use supercell::storage::StoragePool;
+use supercell::vmc::{DidResolver, VerificationMethodCacheTask};
 use tokio::net::TcpListener;
 use tokio::signal;
+use tokio::sync::watch;
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use tracing_subscriber::prelude::*;
 
 use supercell::consumer::ConsumerTask;
 use supercell::consumer::ConsumerTaskConfig;
-use supercell::http::context::WebContext;
-use supercell::http::server::build_router;
+use supercell::http::context::{FeedGeneratorLinks, ReadinessConfig, WebContext};
+use supercell::http::server::{build_admin_router, build_router};
+use supercell::metrics::{ConsumerMetrics, Metrics};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -32,6 +36,14 @@ async fn main() -> Result<()> {
         }
     });
 
+    let args = env::args().collect::<Vec<String>>();
+    match args.get(1).map(String::as_str) {
+        Some("check-config") => std::process::exit(check_config(args.get(2))),
+        Some("test-matchers") => std::process::exit(test_matchers(&args[2..])),
+        Some("publish") => return publish(&args[2..]).await,
+        _ => {}
+    }
+
     let config = supercell::config::Config::new()?;
 
     let mut client_builder = reqwest::Client::builder();
@@ -45,24 +57,50 @@ async fn main() -> Result<()> {
     client_builder = client_builder.user_agent(config.user_agent.clone());
     let http_client = client_builder.build()?;
 
-    let pool = SqlitePool::connect(&config.database_url).await?;
-    sqlx::migrate!().run(&pool).await?;
+    let pool = StoragePool::connect(&config.database_url).await?;
+    pool.migrate().await?;
 
-    let feeds: HashMap<String, (Option<String>, HashSet<String>)> = config
-        .feeds
-        .feeds
-        .iter()
-        .map(|feed| (feed.uri.clone(), (feed.deny.clone(), feed.allow.clone())))
-        .collect();
+    let (feeds_sender, feeds_receiver) = watch::channel(config.feeds.clone());
 
-    let all_dids = feeds
-        .iter()
-        .flat_map(|(_, (_, allow))| allow.iter().cloned())
-        .collect::<HashSet<String>>();
+    match args.get(1).map(String::as_str) {
+        Some("replay") => return replay(&config, pool, feeds_receiver, &args[2..]).await,
+        Some("backfill") => return backfill(&config, pool, &args[2..]).await,
+        _ => {}
+    }
+
+    let metrics = Arc::new(Metrics::new());
 
-    let web_context = WebContext::new(pool.clone(), config.external_base.as_str(), feeds);
+    let web_context = WebContext::new(
+        pool.clone(),
+        config.external_base.as_str(),
+        &config.feeds,
+        FeedGeneratorLinks {
+            privacy_policy: config.privacy_policy_url.clone(),
+            terms_of_service: config.terms_of_service_url.clone(),
+        },
+        metrics.clone(),
+        ReadinessConfig {
+            consumer_enabled: *config.consumer_task_enable.as_ref(),
+            cursor_source: config
+                .jetstream_hostnames
+                .as_ref()
+                .first()
+                .cloned()
+                .unwrap_or_default(),
+            cursor_max_age: *config.readiness_cursor_max_age.as_ref(),
+            vmc_enabled: *config.vmc_task_enable.as_ref(),
+        },
+        DidResolver::new(
+            pool.clone(),
+            http_client.clone(),
+            config.plc_hostname.clone(),
+            *config.did_resolution_rate_limit.as_ref(),
+            *config.did_resolution_negative_ttl.as_ref(),
+            metrics.vmc.clone(),
+        ),
+    );
 
-    let app = build_router(web_context.clone());
+    let app = build_router(web_context.clone(), config.admin_port.is_none());
 
     let tracker = TaskTracker::new();
     let token = CancellationToken::new();
@@ -100,13 +138,13 @@ async fn main() -> Result<()> {
         let inner_config = config.clone();
         let task_enable = *inner_config.consumer_task_enable.as_ref();
         if task_enable {
-            let consumer_task_config = ConsumerTaskConfig {
-                user_agent: inner_config.user_agent.clone(),
-                zstd_dictionary_location: inner_config.zstd_dictionary.clone(),
-                jetstream_hostname: inner_config.jetstream_hostname.clone(),
-                feeds: inner_config.feeds.clone(),
-            };
-            let task = ConsumerTask::new(pool.clone(), consumer_task_config, token.clone())?;
+            let mut task = ConsumerTask::new(
+                pool.clone(),
+                consumer_task_config(&inner_config),
+                feeds_receiver.clone(),
+                metrics.consumer.clone(),
+                token.clone(),
+            )?;
             let inner_token = token.clone();
             tracker.spawn(async move {
                 if let Err(err) = task.run_background().await {
@@ -121,11 +159,12 @@ async fn main() -> Result<()> {
         let inner_config = config.clone();
         let task_enable = *inner_config.vmc_task_enable.as_ref();
         if task_enable {
-            let task = VerificationMethodCacheTask::new(
+            let mut task = VerificationMethodCacheTask::new(
                 pool.clone(),
                 http_client,
                 inner_config.plc_hostname.clone(),
-                all_dids,
+                feeds_receiver.clone(),
+                metrics.vmc.clone(),
                 token.clone(),
             );
             task.main().await?;
@@ -139,6 +178,44 @@ async fn main() -> Result<()> {
         }
     }
 
+    {
+        let inner_config = config.clone();
+        let task_enable = *inner_config.prune_task_enable.as_ref();
+        if task_enable {
+            let task = FeedContentPruneTask::new(
+                pool.clone(),
+                feeds_receiver.clone(),
+                *inner_config.interaction_max_age.as_ref(),
+                token.clone(),
+            );
+            task.main().await?;
+            let interval = *inner_config.prune_interval.as_ref();
+            let inner_token = token.clone();
+            tracker.spawn(async move {
+                if let Err(err) = task.run_background(interval).await {
+                    tracing::warn!(error = ?err, "prune task error");
+                }
+                inner_token.cancel();
+            });
+        }
+    }
+
+    {
+        let task = FeedsReloadTask::new(
+            config.feeds_path.clone(),
+            web_context.clone(),
+            feeds_sender.clone(),
+            token.clone(),
+        );
+        let inner_token = token.clone();
+        tracker.spawn(async move {
+            if let Err(err) = task.run_background().await {
+                tracing::warn!(error = ?err, "feeds reload task error");
+            }
+            inner_token.cancel();
+        });
+    }
+
     {
         let inner_config = config.clone();
         let http_port = *inner_config.http_port.as_ref();
@@ -165,7 +242,306 @@ async fn main() -> Result<()> {
         });
     }
 
+    if let Some(admin_port) = config.admin_port.as_ref() {
+        let admin_port = *admin_port.as_ref();
+        let admin_app = build_admin_router(web_context.clone());
+        let inner_token = token.clone();
+        tracker.spawn(async move {
+            let listener = TcpListener::bind(&format!("0.0.0.0:{}", admin_port))
+                .await
+                .unwrap();
+
+            let shutdown_token = inner_token.clone();
+            let result = axum::serve(listener, admin_app)
+                .with_graceful_shutdown(async move {
+                    shutdown_token.cancelled().await;
+                    tracing::info!("admin axum graceful shutdown complete");
+                })
+                .await;
+            if let Err(err) = result {
+                tracing::error!("admin axum task failed: {}", err);
+            }
+
+            inner_token.cancel();
+        });
+    }
+
     tracker.wait().await;
 
     Ok(())
 }
+
+async fn replay(
+    config: &supercell::config::Config,
+    pool: StoragePool,
+    feeds: watch::Receiver<supercell::config::Feeds>,
+    paths: &[String],
+) -> Result<()> {
+    let task = ConsumerTask::new(
+        pool,
+        ConsumerTaskConfig {
+            archive_directory: None,
+            ..consumer_task_config(config)
+        },
+        feeds,
+        ConsumerMetrics::default(),
+        CancellationToken::new(),
+    )?;
+
+    for path in paths {
+        let count = task.replay(path, &(i64::MIN..i64::MAX)).await?;
+        tracing::info!(path = ?path, count = ?count, "archive replayed");
+    }
+
+    Ok(())
+}
+
+async fn backfill(
+    config: &supercell::config::Config,
+    pool: StoragePool,
+    args: &[String],
+) -> Result<()> {
+    let (Some(feed_uri), Some(from)) = (flag_value(args, "--feed"), flag_value(args, "--from"))
+    else {
+        return Err(anyhow!(
+            "usage: supercell backfill --feed <uri> --from <time> [--to <time>] [--archive <path>]..."
+        ));
+    };
+
+    let from = parse_time(from)?;
+    let to = flag_value(args, "--to")
+        .map(|value| parse_time(value))
+        .transpose()?
+        .unwrap_or_else(|| chrono::Utc::now().timestamp_micros());
+    let window = from..to;
+
+    let feed = config
+        .feeds
+        .feeds
+        .iter()
+        .find(|feed| &feed.uri == feed_uri)
+        .ok_or(anyhow!("feed {} is not configured", feed_uri))?;
+
+    let (_feeds_sender, feeds_receiver) = watch::channel(supercell::config::Feeds {
+        feeds: vec![feed.clone()],
+    });
+
+    let token = CancellationToken::new();
+    {
+        let inner_token = token.clone();
+        tokio::spawn(async move {
+            if signal::ctrl_c().await.is_ok() {
+                inner_token.cancel();
+            }
+        });
+    }
+
+    let task = ConsumerTask::new(
+        pool,
+        ConsumerTaskConfig {
+            archive_directory: None,
+            ..consumer_task_config(config)
+        },
+        feeds_receiver,
+        ConsumerMetrics::default(),
+        token,
+    )?;
+
+    let archives = flag_values(args, "--archive");
+    let count = if archives.is_empty() {
+        task.backfill(&format!("backfill:{}", feed_uri), &window)
+            .await?
+    } else {
+        let mut count = 0;
+        for path in archives {
+            count += task.replay(path, &window).await?;
+        }
+        count
+    };
+
+    tracing::info!(feed_id = ?feed_uri, count = ?count, "backfill complete");
+
+    Ok(())
+}
+
+/// Publishes a feed generator record for every configured feed. Only
+/// `EXTERNAL_BASE` and `FEEDS` are read from the environment, along with
+/// `PUBLISH_PASSWORD` when `--password` is not given.
+async fn publish(args: &[String]) -> Result<()> {
+    let Some(identifier) = flag_value(args, "--identifier") else {
+        return Err(anyhow!(
+            "usage: supercell publish --identifier <handle> [--password <app password>] [--pds <url>] [--avatar <path>]"
+        ));
+    };
+
+    let password = match flag_value(args, "--password") {
+        Some(password) => password.clone(),
+        None => env::var("PUBLISH_PASSWORD")
+            .map_err(|_| anyhow!("one of --password or PUBLISH_PASSWORD must be set"))?,
+    };
+
+    let account = PublishAccount {
+        pds: flag_value(args, "--pds")
+            .cloned()
+            .unwrap_or_else(|| "https://bsky.social".to_string()),
+        identifier: identifier.clone(),
+        password,
+    };
+
+    let external_base =
+        env::var("EXTERNAL_BASE").map_err(|_| anyhow!("EXTERNAL_BASE must be set"))?;
+    let feeds: supercell::config::Feeds = env::var("FEEDS")
+        .map_err(|_| anyhow!("FEEDS must be set"))?
+        .try_into()?;
+
+    let http_client = reqwest::Client::builder()
+        .user_agent(format!(
+            "supercell ({}; +https://github.com/astrenoxcoop/supercell)",
+            supercell::config::version()?
+        ))
+        .build()?;
+
+    let published = publish_feeds(
+        &http_client,
+        &account,
+        &external_base,
+        &feeds,
+        flag_value(args, "--avatar").map(String::as_str),
+    )
+    .await?;
+
+    for uri in published {
+        println!("{}", uri);
+    }
+
+    Ok(())
+}
+
+/// Parses an RFC 3339 timestamp, or a duration such as `7d` meaning that long
+/// ago, into microseconds since the epoch.
+fn parse_time(value: &str) -> Result<i64> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp_micros());
+    }
+
+    let ago = humantime::parse_duration(value)
+        .map_err(|err| anyhow::Error::new(err).context(anyhow!("invalid time {}", value)))?;
+    let ago = chrono::Duration::from_std(ago)?;
+    Ok((chrono::Utc::now() - ago).timestamp_micros())
+}
+
+fn consumer_task_config(config: &supercell::config::Config) -> ConsumerTaskConfig {
+    ConsumerTaskConfig {
+        user_agent: config.user_agent.clone(),
+        zstd_dictionary_location: config.zstd_dictionary.clone(),
+        jetstream_hostnames: config.jetstream_hostnames.as_ref().clone(),
+        checkpoint_interval: *config.consumer_checkpoint_interval.as_ref(),
+        authors_refresh_interval: *config.consumer_authors_refresh_interval.as_ref(),
+        cursor_rewind: *config.consumer_cursor_rewind.as_ref(),
+        archive_directory: config.archive_directory.clone(),
+        batch_size: *config.consumer_batch_size.as_ref(),
+        batch_interval: *config.consumer_batch_interval.as_ref(),
+    }
+}
+
+fn check_config(path: Option<&String>) -> i32 {
+    let Some(path) = path else {
+        eprintln!("usage: supercell check-config <path>");
+        return 2;
+    };
+
+    let feeds: supercell::config::Feeds = match path.clone().try_into() {
+        Ok(feeds) => feeds,
+        Err(err) => {
+            eprintln!("{}: {:#}", path, err);
+            return 1;
+        }
+    };
+
+    let problems = supercell::check::check_feeds(&feeds);
+    if problems.is_empty() {
+        println!("{}: ok ({} feeds)", path, feeds.feeds.len());
+        return 0;
+    }
+
+    eprintln!("{}: {} problems found", path, problems.len());
+    for problem in problems {
+        eprintln!("  - {}", problem);
+    }
+    1
+}
+
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+}
+
+fn flag_values<'a>(args: &'a [String], name: &str) -> Vec<&'a String> {
+    args.iter()
+        .zip(args.iter().skip(1))
+        .filter(|(arg, _)| *arg == name)
+        .map(|(_, value)| value)
+        .collect()
+}
+
+fn test_matchers(args: &[String]) -> i32 {
+    let (Some(feeds_path), Some(events_path)) =
+        (flag_value(args, "--feeds"), flag_value(args, "--events"))
+    else {
+        eprintln!("usage: supercell test-matchers --feeds <path> --events <path>");
+        return 2;
+    };
+
+    let feeds: supercell::config::Feeds = match feeds_path.clone().try_into() {
+        Ok(feeds) => feeds,
+        Err(err) => {
+            eprintln!("{}: {:#}", feeds_path, err);
+            return 1;
+        }
+    };
+
+    let events = match std::fs::File::open(events_path) {
+        Ok(events) => std::io::BufReader::new(events),
+        Err(err) => {
+            eprintln!("{}: {}", events_path, err);
+            return 1;
+        }
+    };
+
+    let report = match supercell::harness::test_matchers(&feeds, events) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{:#}", err);
+            return 1;
+        }
+    };
+
+    for (line, error) in report.errors.iter() {
+        eprintln!("{}:{}: {}", events_path, line, error);
+    }
+
+    println!("{} events", report.events);
+    for feed_report in report.feeds {
+        println!(
+            "{}: {} matched",
+            feed_report.feed,
+            feed_report.matches.len()
+        );
+        for event_match in feed_report.matches {
+            println!(
+                "  line {} {} matcher {} ({})",
+                event_match.line,
+                event_match.uri.as_deref().unwrap_or("-"),
+                event_match.matcher,
+                event_match.matcher_kind
+            );
+        }
+    }
+
+    if report.errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+
+use crate::config;
+use crate::matcher::FeedMatcher;
+
+/// Fails with every problem `check_feeds` finds. Used wherever a feeds file is
+/// loaded, so that startup and reloads accept the same files.
+pub fn validate_feeds(config_feeds: &config::Feeds) -> Result<()> {
+    let problems = check_feeds(config_feeds);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!("invalid feeds file: {}", problems.join("; ")))
+}
+
+/// Checks a feeds configuration for problems that would otherwise only be
+/// found at runtime, returning a description of each problem found.
+pub fn check_feeds(config_feeds: &config::Feeds) -> Vec<String> {
+    let did_regex =
+        Regex::new(r"^did:[a-z]+:[a-zA-Z0-9._:%-]*[a-zA-Z0-9._-]$").expect("did regex is valid");
+
+    let mut problems = vec![];
+    let mut seen_uris = HashSet::new();
+
+    for config_feed in config_feeds.feeds.iter() {
+        let uri = &config_feed.uri;
+
+        if !seen_uris.insert(uri.clone()) {
+            problems.push(format!("feed {}: duplicate feed uri", uri));
+        }
+
+        if let Err(reason) = check_aturi(uri, &did_regex) {
+            problems.push(format!("feed {}: invalid feed uri: {}", uri, reason));
+        }
+
+        if let Some(deny) = &config_feed.deny {
+            if let Err(reason) = check_aturi(deny, &did_regex) {
+                problems.push(format!(
+                    "feed {}: invalid deny uri {}: {}",
+                    uri, deny, reason
+                ));
+            }
+        }
+
+        for did in config_feed.allow.iter() {
+            if !did_regex.is_match(did) {
+                problems.push(format!("feed {}: allow entry {} is not a DID", uri, did));
+            }
+        }
+
+        for did in config_feed.authors.iter() {
+            if !did_regex.is_match(did) {
+                problems.push(format!("feed {}: authors entry {} is not a DID", uri, did));
+            }
+        }
+
+        if let Err(err) = FeedMatcher::from_config(config_feed) {
+            problems.push(format!("feed {}: {:#}", uri, err));
+        }
+    }
+
+    problems
+}
+
+fn check_aturi(value: &str, did_regex: &Regex) -> Result<(), &'static str> {
+    let path = value.strip_prefix("at://").ok_or("must start with at://")?;
+
+    let parts = path.split('/').collect::<Vec<&str>>();
+    if parts.len() != 3 || parts.iter().any(|part| part.is_empty()) {
+        return Err("must be at://<did>/<collection>/<rkey>");
+    }
+
+    if !did_regex.is_match(parts[0]) {
+        return Err("authority is not a DID");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_feeds_problems() {
+        let config_yaml = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/3la5azib4xe2c"
+  name: "Valid"
+  description: "Valid"
+  allow: ["did:plc:cbkjy5n7bk3ax2wplmtjofq2", "did:web:feeds.example.com"]
+  deny: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.post/3la5bsyzj3j23"
+  matchers:
+  - path: "$.did"
+    value: "did:plc:tgudj2fjm77pzkuawquqhsxm"
+    type: equal
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/3la5azib4xe2c"
+  name: "Duplicate"
+  description: "Duplicate"
+  matchers: []
+- uri: "https://example.com/feed"
+  name: "Not an AT-URI"
+  description: "Not an AT-URI"
+  allow: ["ngerakines.me"]
+  matchers:
+  - path: "$.commit.record.text["
+    value: "foo"
+    type: prefix
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/empty"
+  name: "Empty any"
+  description: "Empty any"
+  matchers:
+  - type: any
+    matchers: []
+"#;
+
+        let config_feeds: config::Feeds =
+            serde_yaml::from_str(config_yaml).expect("config is valid");
+
+        let problems = check_feeds(&config_feeds);
+        assert_eq!(problems.len(), 5, "{:?}", problems);
+        assert!(problems[0].contains("duplicate feed uri"));
+        assert!(problems[1].contains("must start with at://"));
+        assert!(problems[2].contains("ngerakines.me is not a DID"));
+        assert!(problems[3].contains("cannot parse path"));
+        assert!(problems[4].contains("any matcher must have at least one matcher"));
+
+        assert!(check_feeds(&config::Feeds {
+            feeds: config_feeds.feeds[..1].to_vec(),
+        })
+        .is_empty());
+    }
+}
@@ -20,9 +20,31 @@ pub struct Feed {
     #[serde(default)]
     pub deny: Option<String>,
 
+    #[serde(default = "default_collections")]
+    pub collections: Vec<String>,
+
+    #[serde(default)]
+    pub authors: HashSet<String>,
+
+    #[serde(default)]
+    pub authors_file: Option<String>,
+
+    #[serde(default)]
+    pub max_items: Option<u32>,
+
+    #[serde(default)]
+    pub max_age: Option<HumanDuration>,
+
+    #[serde(default)]
+    pub hide_seen: bool,
+
     pub matchers: Vec<Matcher>,
 }
 
+fn default_collections() -> Vec<String> {
+    vec!["app.bsky.feed.post".to_string()]
+}
+
 #[derive(Clone, Deserialize)]
 #[serde(tag = "type")]
 pub enum Matcher {
@@ -34,6 +56,32 @@ pub enum Matcher {
 
     #[serde(rename = "sequence")]
     Sequence { path: String, values: Vec<String> },
+
+    #[serde(rename = "regex")]
+    Regex { path: String, pattern: String },
+
+    #[serde(rename = "all")]
+    All { matchers: Vec<Matcher> },
+
+    #[serde(rename = "any")]
+    Any { matchers: Vec<Matcher> },
+
+    #[serde(rename = "not")]
+    Not { matcher: Box<Matcher> },
+}
+
+impl Matcher {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Matcher::Equal { .. } => "equal",
+            Matcher::Prefix { .. } => "prefix",
+            Matcher::Sequence { .. } => "sequence",
+            Matcher::Regex { .. } => "regex",
+            Matcher::All { .. } => "all",
+            Matcher::Any { .. } => "any",
+            Matcher::Not { .. } => "not",
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -45,25 +93,59 @@ pub struct CertificateBundles(Vec<String>);
 #[derive(Clone)]
 pub struct TaskEnable(bool);
 
+#[derive(Clone)]
+pub struct JetstreamHostnames(Vec<String>);
+
+#[derive(Clone)]
+pub struct BatchSize(usize);
+
+#[derive(Clone)]
+pub struct RateLimit(u32);
+
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HumanDuration(std::time::Duration);
+
 #[derive(Clone)]
 pub struct Config {
     pub version: String,
     pub http_port: HttpPort,
+    pub admin_port: Option<HttpPort>,
     pub external_base: String,
     pub database_url: String,
     pub certificate_bundles: CertificateBundles,
     pub consumer_task_enable: TaskEnable,
     pub vmc_task_enable: TaskEnable,
+    pub prune_task_enable: TaskEnable,
+    pub prune_interval: HumanDuration,
+    pub interaction_max_age: HumanDuration,
     pub plc_hostname: String,
+    pub did_resolution_rate_limit: RateLimit,
+    pub did_resolution_negative_ttl: HumanDuration,
     pub user_agent: String,
     pub zstd_dictionary: String,
-    pub jetstream_hostname: String,
+    pub jetstream_hostnames: JetstreamHostnames,
+    pub consumer_checkpoint_interval: HumanDuration,
+    pub consumer_authors_refresh_interval: HumanDuration,
+    pub consumer_cursor_rewind: HumanDuration,
+    pub consumer_batch_size: BatchSize,
+    pub consumer_batch_interval: HumanDuration,
+    pub readiness_cursor_max_age: HumanDuration,
+    pub privacy_policy_url: Option<String>,
+    pub terms_of_service_url: Option<String>,
+    pub archive_directory: Option<String>,
+    pub feeds_path: String,
     pub feeds: Feeds,
 }
 
 impl Config {
     pub fn new() -> Result<Self> {
         let http_port: HttpPort = default_env("HTTP_PORT", "4050").try_into()?;
+        let admin_port: Option<HttpPort> = Some(optional_env("ADMIN_PORT"))
+            .filter(|value| !value.is_empty())
+            .map(HttpPort::try_from)
+            .transpose()?;
+
         let external_base = require_env("EXTERNAL_BASE")?;
 
         let database_url = default_env("DATABASE_URL", "sqlite://development.db");
@@ -71,16 +153,67 @@ impl Config {
         let certificate_bundles: CertificateBundles =
             optional_env("CERTIFICATE_BUNDLES").try_into()?;
 
-        let jetstream_hostname = require_env("JETSTREAM_HOSTNAME")?;
+        let jetstream_hostnames: JetstreamHostnames =
+            require_env("JETSTREAM_HOSTNAME")?.try_into()?;
         let zstd_dictionary = require_env("ZSTD_DICTIONARY")?;
 
         let consumer_task_enable: TaskEnable =
             default_env("CONSUMER_TASK_ENABLE", "true").try_into()?;
 
+        let consumer_checkpoint_interval: HumanDuration =
+            default_env("CONSUMER_CHECKPOINT_INTERVAL", "120s").try_into()?;
+        if consumer_checkpoint_interval.as_ref().is_zero() {
+            return Err(anyhow!(
+                "CONSUMER_CHECKPOINT_INTERVAL must be greater than zero"
+            ));
+        }
+
+        let consumer_authors_refresh_interval: HumanDuration =
+            default_env("CONSUMER_AUTHORS_REFRESH_INTERVAL", "120s").try_into()?;
+        if consumer_authors_refresh_interval.as_ref().is_zero() {
+            return Err(anyhow!(
+                "CONSUMER_AUTHORS_REFRESH_INTERVAL must be greater than zero"
+            ));
+        }
+
+        let consumer_cursor_rewind: HumanDuration =
+            default_env("CONSUMER_CURSOR_REWIND", "0s").try_into()?;
+
+        let consumer_batch_size: BatchSize =
+            default_env("CONSUMER_BATCH_SIZE", "100").try_into()?;
+
+        let consumer_batch_interval: HumanDuration =
+            default_env("CONSUMER_BATCH_INTERVAL", "1s").try_into()?;
+
+        let readiness_cursor_max_age: HumanDuration =
+            default_env("READINESS_CURSOR_MAX_AGE", "10m").try_into()?;
+
+        let archive_directory = Some(optional_env("ARCHIVE_DIRECTORY")).filter(|s| !s.is_empty());
+
+        let privacy_policy_url = Some(optional_env("PRIVACY_POLICY_URL")).filter(|s| !s.is_empty());
+        let terms_of_service_url =
+            Some(optional_env("TERMS_OF_SERVICE_URL")).filter(|s| !s.is_empty());
+
         let vmc_task_enable: TaskEnable = default_env("VMC_TASK_ENABLE", "true").try_into()?;
 
+        let prune_task_enable: TaskEnable = default_env("PRUNE_TASK_ENABLE", "true").try_into()?;
+
+        let prune_interval: HumanDuration = default_env("PRUNE_INTERVAL", "15m").try_into()?;
+        if prune_interval.as_ref().is_zero() {
+            return Err(anyhow!("PRUNE_INTERVAL must be greater than zero"));
+        }
+
+        let interaction_max_age: HumanDuration =
+            default_env("INTERACTION_MAX_AGE", "7d").try_into()?;
+
         let plc_hostname = default_env("PLC_HOSTNAME", "plc.directory");
 
+        let did_resolution_rate_limit: RateLimit =
+            default_env("DID_RESOLUTION_RATE_LIMIT", "60").try_into()?;
+
+        let did_resolution_negative_ttl: HumanDuration =
+            default_env("DID_RESOLUTION_NEGATIVE_TTL", "5m").try_into()?;
+
         let default_user_agent = format!(
             "supercell ({}; +https://github.com/astrenoxcoop/supercell)",
             version()?
@@ -88,20 +221,38 @@ impl Config {
 
         let user_agent = default_env("USER_AGENT", &default_user_agent);
 
-        let feeds: Feeds = require_env("FEEDS")?.try_into()?;
+        let feeds_path = require_env("FEEDS")?;
+        let feeds: Feeds = feeds_path.clone().try_into()?;
+        crate::check::validate_feeds(&feeds)?;
 
         Ok(Self {
             version: version()?,
             http_port,
+            admin_port,
             external_base,
             database_url,
             certificate_bundles,
             consumer_task_enable,
             vmc_task_enable,
+            prune_task_enable,
+            prune_interval,
+            interaction_max_age,
             plc_hostname,
+            did_resolution_rate_limit,
+            did_resolution_negative_ttl,
             user_agent,
-            jetstream_hostname,
+            jetstream_hostnames,
+            consumer_checkpoint_interval,
+            consumer_authors_refresh_interval,
+            consumer_cursor_rewind,
+            consumer_batch_size,
+            consumer_batch_interval,
+            readiness_cursor_max_age,
+            privacy_policy_url,
+            terms_of_service_url,
+            archive_directory,
             zstd_dictionary,
+            feeds_path,
             feeds,
         })
     }
@@ -186,6 +337,82 @@ impl TryFrom<String> for TaskEnable {
     }
 }
 
+impl TryFrom<String> for JetstreamHostnames {
+    type Error = anyhow::Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let hostnames = value
+            .split(';')
+            .filter_map(|s| {
+                if s.is_empty() {
+                    None
+                } else {
+                    Some(s.to_string())
+                }
+            })
+            .collect::<Vec<String>>();
+        if hostnames.is_empty() {
+            return Err(anyhow!("at least one jetstream hostname must be set"));
+        }
+        Ok(Self(hostnames))
+    }
+}
+
+impl AsRef<Vec<String>> for JetstreamHostnames {
+    fn as_ref(&self) -> &Vec<String> {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for BatchSize {
+    type Error = anyhow::Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.parse::<usize>().map_err(|err| {
+            anyhow::Error::new(err).context(anyhow!("parsing batch size into usize failed"))
+        })?;
+        if value == 0 {
+            return Err(anyhow!("batch size must be greater than zero"));
+        }
+        Ok(Self(value))
+    }
+}
+
+impl AsRef<usize> for BatchSize {
+    fn as_ref(&self) -> &usize {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for RateLimit {
+    type Error = anyhow::Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.parse::<u32>().map_err(|err| {
+            anyhow::Error::new(err).context(anyhow!("parsing rate limit into u32 failed"))
+        })?;
+        Ok(Self(value))
+    }
+}
+
+impl AsRef<u32> for RateLimit {
+    fn as_ref(&self) -> &u32 {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for HumanDuration {
+    type Error = anyhow::Error;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        humantime::parse_duration(&value)
+            .map(Self)
+            .map_err(|err| anyhow::Error::new(err).context(anyhow!("parsing duration failed")))
+    }
+}
+
+impl AsRef<std::time::Duration> for HumanDuration {
+    fn as_ref(&self) -> &std::time::Duration {
+        &self.0
+    }
+}
+
 impl TryFrom<String> for Feeds {
     type Error = anyhow::Error;
     fn try_from(value: String) -> Result<Self, Self::Error> {
@@ -1,68 +1,446 @@
+use std::io::BufRead;
+use std::ops::Range;
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use futures_util::SinkExt;
 use futures_util::StreamExt;
 use http::HeaderValue;
 use http::Uri;
-use tokio::time::{sleep, Instant};
+use prometheus_client::metrics::counter::Counter;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, sleep_until, Instant, Sleep};
 use tokio_util::sync::CancellationToken;
-use tokio_websockets::{ClientBuilder, Message};
+use tokio_websockets::{ClientBuilder, MaybeTlsStream, Message, WebSocketStream};
 
+use crate::archive::{open_archive, ArchiveWriter};
 use crate::config;
-use crate::matcher::FeedMatchers;
-use crate::storage;
+use crate::matcher::{read_authors, FeedMatchers};
+use crate::metrics::{ConsumerMetrics, FeedLabels};
 use crate::storage::consumer_control_get;
-use crate::storage::consumer_control_insert;
-use crate::storage::feed_content_insert;
+use crate::storage::feed_content_write_batch;
+use crate::storage::model::{FeedContent, FeedContentWrite};
 use crate::storage::StoragePool;
 
 const MAX_MESSAGE_SIZE: usize = 25000;
 
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+/// The number of consecutive failed connections before moving on to the next
+/// configured jetstream hostname.
+const MAX_HOSTNAME_FAILURES: u32 = 3;
+
+/// The number of items each pipeline stage can queue for the next stage.
+const PIPELINE_CHANNEL_SIZE: usize = 1024;
+
+const WRITE_RETRY_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const WRITE_RETRY_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+type JetstreamClient = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+enum Disconnect {
+    Cancelled,
+    Closed,
+
+    /// A later pipeline stage stopped and can no longer accept messages.
+    Stopped,
+}
+
 #[derive(Clone)]
 pub struct ConsumerTaskConfig {
     pub user_agent: String,
     pub zstd_dictionary_location: String,
-    pub jetstream_hostname: String,
-    pub feeds: config::Feeds,
+    pub jetstream_hostnames: Vec<String>,
+    pub checkpoint_interval: Duration,
+    pub authors_refresh_interval: Duration,
+    pub cursor_rewind: Duration,
+    pub archive_directory: Option<String>,
+    pub batch_size: usize,
+    pub batch_interval: Duration,
 }
 
 pub struct ConsumerTask {
     cancellation_token: CancellationToken,
     pool: StoragePool,
     config: ConsumerTaskConfig,
-    feed_matchers: FeedMatchers,
+    feeds: watch::Receiver<config::Feeds>,
+    feed_matchers: Arc<RwLock<FeedMatchers>>,
+    archive: Option<ArchiveWriter>,
+    metrics: ConsumerMetrics,
+
+    /// The time of the newest event decoded, used as the cursor when reconnecting.
+    time_usec: Arc<AtomicI64>,
+}
+
+/// The feed content changes for a single event, passed from the match stage
+/// to the write stage.
+struct MatchedEvent {
+    time_us: i64,
+    writes: Vec<FeedContentWrite>,
 }
 
 impl ConsumerTask {
     pub fn new(
         pool: StoragePool,
         config: ConsumerTaskConfig,
+        feeds: watch::Receiver<config::Feeds>,
+        metrics: ConsumerMetrics,
         cancellation_token: CancellationToken,
     ) -> Result<Self> {
-        let feed_matchers = FeedMatchers::from_config(&config.feeds)?;
+        let feed_matchers = FeedMatchers::from_config(&feeds.borrow())?;
+
+        let archive = config
+            .archive_directory
+            .as_ref()
+            .map(|directory| ArchiveWriter::new(directory))
+            .transpose()?;
 
         Ok(Self {
             pool,
             cancellation_token,
             config,
-            feed_matchers,
+            feeds,
+            feed_matchers: Arc::new(RwLock::new(feed_matchers)),
+            archive,
+            metrics,
+            time_usec: Arc::default(),
         })
     }
 
-    pub async fn run_background(&self) -> Result<()> {
+    /// Runs the consumer pipeline until cancelled. Websocket messages are read,
+    /// decoded, matched, and written by separate stages connected by bounded
+    /// channels, so a slow database slows reads instead of stalling them
+    /// outright. When the reader stops, each later stage drains its channel
+    /// before stopping.
+    pub async fn run_background(&mut self) -> Result<()> {
         tracing::debug!("ConsumerTask started");
 
-        let last_time_us =
-            consumer_control_get(&self.pool, &self.config.jetstream_hostname).await?;
+        let cursor_source = self
+            .config
+            .jetstream_hostnames
+            .first()
+            .cloned()
+            .ok_or(anyhow!("no jetstream hostnames configured"))?;
 
         // mkdir -p data/ && curl -o data/zstd_dictionary https://github.com/bluesky-social/jetstream/raw/refs/heads/main/pkg/models/zstd_dictionary
         let data: Vec<u8> = std::fs::read(self.config.zstd_dictionary_location.clone())
             .context("unable to load zstd dictionary")?;
 
+        let decompressor = zstd::bulk::Decompressor::with_dictionary(&data)
+            .map_err(|err| anyhow::Error::msg(err).context("cannot create decompressor"))?;
+
+        let match_workers = std::thread::available_parallelism()
+            .map(|workers| workers.get())
+            .unwrap_or(1);
+
+        let (message_sender, message_receiver) = mpsc::channel(PIPELINE_CHANNEL_SIZE);
+        let (event_sender, event_receiver) = mpsc::channel(PIPELINE_CHANNEL_SIZE);
+        let (matched_sender, matched_receiver) = mpsc::channel(PIPELINE_CHANNEL_SIZE);
+
+        let (archive_sender, archive) = match self.archive.take() {
+            Some(archive) => {
+                let (archive_sender, archive_receiver) = mpsc::channel(PIPELINE_CHANNEL_SIZE);
+                let flush_interval = self.config.checkpoint_interval;
+                let archive = tokio::task::spawn_blocking(move || {
+                    archive_stage(archive, flush_interval, archive_receiver)
+                });
+                (Some(archive_sender), Some(archive))
+            }
+            None => (None, None),
+        };
+
+        let decode = decode_stage(
+            decompressor,
+            archive_sender,
+            message_receiver,
+            event_sender,
+            self.time_usec.clone(),
+            self.metrics.clone(),
+        );
+        let match_events = match_stage(
+            self.feed_matchers.clone(),
+            match_workers,
+            event_receiver,
+            matched_sender,
+            self.metrics.clone(),
+        );
+        let write = write_stage(
+            self.pool.clone(),
+            cursor_source.clone(),
+            self.config.clone(),
+            matched_receiver,
+            self.cancellation_token.clone(),
+            self.metrics.clone(),
+        );
+        let read = self.read_stage(&cursor_source, message_sender);
+
+        let (read, decode, match_events, write) = tokio::join!(read, decode, match_events, write);
+
+        // The decode stage has stopped, so the archive stage has every message
+        // and finishes its files once its channel is drained.
+        let archive = match archive {
+            Some(archive) => archive
+                .await
+                .context("consumer archive stage panicked")
+                .and_then(|result| result),
+            None => Ok(()),
+        };
+
+        tracing::debug!("ConsumerTask stopped");
+
+        read.context("consumer read stage failed")?;
+        decode.context("consumer decode stage failed")?;
+        archive.context("consumer archive stage failed")?;
+        match_events.context("consumer match stage failed")?;
+        write.context("consumer write stage failed")
+    }
+
+    /// Connects to jetstream, reconnecting with backoff and rotating through
+    /// the configured hostnames, and passes every message to the decode stage.
+    async fn read_stage(
+        &mut self,
+        cursor_source: &str,
+        sender: mpsc::Sender<Message>,
+    ) -> Result<()> {
+        let hostnames = self.config.jetstream_hostnames.clone();
+
+        let sleeper = sleep(self.config.checkpoint_interval);
+        tokio::pin!(sleeper);
+
+        let authors_sleeper = sleep(self.config.authors_refresh_interval);
+        tokio::pin!(authors_sleeper);
+
+        let mut reconnect = Reconnect::default();
+
+        loop {
+            let hostname = &hostnames[reconnect.hostname_index];
+
+            let last_time_usec = self.time_usec.load(Ordering::Relaxed);
+
+            let connected = match self.start_cursor(cursor_source, last_time_usec).await {
+                Ok(cursor) => self
+                    .connect(hostname, cursor)
+                    .await
+                    .map(|client| (client, cursor)),
+                Err(err) => Err(err),
+            };
+
+            match connected {
+                Ok((mut client, cursor)) => {
+                    tracing::info!(hostname = ?hostname, cursor = ?cursor, "connected to jetstream");
+                    self.metrics.connected.set(1);
+
+                    let disconnect = self
+                        .read(
+                            &mut client,
+                            &sender,
+                            sleeper.as_mut(),
+                            authors_sleeper.as_mut(),
+                        )
+                        .await;
+                    self.metrics.connected.set(0);
+
+                    match disconnect {
+                        Disconnect::Cancelled => break,
+                        Disconnect::Stopped => {
+                            return Err(anyhow!("consumer pipeline stopped unexpectedly"));
+                        }
+                        Disconnect::Closed => {
+                            tracing::warn!(hostname = ?hostname, "jetstream connection closed");
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(error = ?err, hostname = ?hostname, "cannot connect to jetstream");
+                }
+            }
+
+            let progressed = self.time_usec.load(Ordering::Relaxed) > last_time_usec;
+            let (next, delay) = reconnect.next(progressed, hostnames.len());
+            if next.hostname_index != reconnect.hostname_index {
+                tracing::warn!(hostname = ?hostnames[next.hostname_index], "rotating jetstream hostname");
+            }
+            reconnect = next;
+            let delay = jitter(delay);
+
+            tracing::info!(delay = ?delay, "reconnecting to jetstream");
+
+            tokio::select! {
+                () = self.cancellation_token.cancelled() => {
+                    break;
+                },
+                () = sleep(delay) => { },
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_event(&self, event: &model::Event, writes: &mut Vec<FeedContentWrite>) {
+        let feed_matchers = self
+            .feed_matchers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        match_event(&feed_matchers, event, writes);
+    }
+
+    /// Writes queued feed content changes, and the cursor when given, in one
+    /// transaction.
+    async fn flush(
+        &self,
+        writes: &mut Vec<FeedContentWrite>,
+        cursor: Option<(&str, i64)>,
+    ) -> Result<()> {
+        if writes.is_empty() && cursor.is_none() {
+            return Ok(());
+        }
+        feed_content_write_batch(&self.pool, writes, cursor).await?;
+        writes.clear();
+        Ok(())
+    }
+
+    /// Replays an archive of recorded events through the same matching path
+    /// used for live events. Only events within the window are processed and
+    /// the consumer cursor is not changed.
+    pub async fn replay(&self, path: &str, window: &Range<i64>) -> Result<usize> {
+        let archive = open_archive(path)?;
+
+        // Decompressing and parsing happen on a blocking thread.
+        let (sender, mut receiver) = mpsc::channel(PIPELINE_CHANNEL_SIZE);
+        let reader = {
+            let path = path.to_string();
+            let window = window.clone();
+            tokio::task::spawn_blocking(move || read_archive(archive, &path, &window, sender))
+        };
+
+        let mut writes = Vec::new();
+        let mut count = 0;
+        loop {
+            tokio::select! {
+                () = self.cancellation_token.cancelled() => {
+                    break;
+                },
+                event = receiver.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+
+                    self.process_event(&event, &mut writes);
+                    count += 1;
+
+                    if writes.len() >= self.config.batch_size {
+                        self.flush(&mut writes, None).await?;
+                    }
+                }
+            }
+        }
+
+        drop(receiver);
+        reader.await.context("archive reader panicked")?;
+
+        self.flush(&mut writes, None).await?;
+
+        Ok(count)
+    }
+
+    /// Consumes jetstream from the start of the window until the first event
+    /// past its end. Progress is stored in `consumer_control` under the given
+    /// source so that the live consumer cursor is not disturbed and an
+    /// interrupted backfill resumes where it stopped.
+    pub async fn backfill(&self, cursor_source: &str, window: &Range<i64>) -> Result<usize> {
+        let hostname = self
+            .config
+            .jetstream_hostnames
+            .first()
+            .ok_or(anyhow!("no jetstream hostnames configured"))?;
+
+        let data: Vec<u8> = std::fs::read(self.config.zstd_dictionary_location.clone())
+            .context("unable to load zstd dictionary")?;
+
+        let mut decompressor = zstd::bulk::Decompressor::with_dictionary(&data)
+            .map_err(|err| anyhow::Error::msg(err).context("cannot create decompressor"))?;
+
+        let cursor = consumer_control_get(&self.pool, cursor_source)
+            .await?
+            .filter(|value| window.contains(value))
+            .unwrap_or(window.start);
+
+        let mut client = self.connect(hostname, Some(cursor)).await?;
+        tracing::info!(hostname = ?hostname, cursor = ?cursor, "backfilling from jetstream");
+
+        let sleeper = sleep(self.config.checkpoint_interval);
+        tokio::pin!(sleeper);
+
+        let mut time_usec = cursor;
+        let mut writes = Vec::new();
+        let mut count = 0;
+
+        let result = loop {
+            tokio::select! {
+                () = self.cancellation_token.cancelled() => {
+                    break Ok(count);
+                },
+                () = &mut sleeper => {
+                    self.flush(&mut writes, Some((cursor_source, time_usec))).await?;
+                    tracing::info!(time_us = ?time_usec, count = ?count, "backfill progress");
+                    sleeper.as_mut().reset(Instant::now() + self.config.checkpoint_interval);
+                },
+                item = client.next() => {
+                    let Some(item) = item else {
+                        break Err(anyhow!("jetstream connection closed during backfill"));
+                    };
+
+                    let item = match item {
+                        Ok(item) => item,
+                        Err(err) => {
+                            tracing::error!(error = ?err, "error processing jetstream message");
+                            self.metrics.decode_error("websocket");
+                            continue;
+                        }
+                    };
+
+                    let Some(decoded) = decompress_message(&mut decompressor, item, &self.metrics) else {
+                        continue;
+                    };
+
+                    let Some(event) = parse_event(&decoded, &self.metrics) else {
+                        continue;
+                    };
+
+                    if event.time_us >= window.end {
+                        break Ok(count);
+                    }
+
+                    time_usec = std::cmp::max(time_usec, event.time_us);
+
+                    if window.contains(&event.time_us) {
+                        self.process_event(&event, &mut writes);
+                        count += 1;
+                    }
+
+                    if writes.len() >= self.config.batch_size {
+                        self.flush(&mut writes, Some((cursor_source, time_usec))).await?;
+                    }
+                }
+            }
+        };
+
+        self.flush(&mut writes, Some((cursor_source, time_usec)))
+            .await?;
+
+        result
+    }
+
+    async fn connect(&self, hostname: &str, cursor: Option<i64>) -> Result<JetstreamClient> {
         let uri = Uri::from_str(&format!(
             "wss://{}/subscribe?compress=true&requireHello=true",
-            self.config.jetstream_hostname
+            hostname
         ))
         .context("invalid jetstream URL")?;
 
@@ -75,11 +453,32 @@ impl ConsumerTask {
             .await
             .map_err(|err| anyhow::Error::new(err).context("cannot connect to jetstream"))?;
 
+        self.send_options_update(&mut client, cursor).await?;
+
+        Ok(client)
+    }
+
+    async fn send_options_update(
+        &self,
+        client: &mut JetstreamClient,
+        cursor: Option<i64>,
+    ) -> Result<()> {
+        let (wanted_collections, wanted_dids) = {
+            let feed_matchers = self
+                .feed_matchers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            (
+                feed_matchers.wanted_collections(),
+                feed_matchers.wanted_dids().unwrap_or_default(),
+            )
+        };
+
         let update = model::SubscriberSourcedMessage::Update {
-            wanted_collections: vec!["app.bsky.feed.post".to_string()],
-            wanted_dids: vec![],
+            wanted_collections,
+            wanted_dids,
             max_message_size_bytes: MAX_MESSAGE_SIZE as u64,
-            cursor: last_time_us,
+            cursor,
         };
         let serialized_update = serde_json::to_string(&update)
             .map_err(|err| anyhow::Error::msg(err).context("cannot serialize update"))?;
@@ -87,103 +486,605 @@ impl ConsumerTask {
         client
             .send(Message::text(serialized_update))
             .await
-            .map_err(|err| anyhow::Error::msg(err).context("cannot send update"))?;
-
-        let mut decompressor = zstd::bulk::Decompressor::with_dictionary(&data)
-            .map_err(|err| anyhow::Error::msg(err).context("cannot create decompressor"))?;
+            .map_err(|err| anyhow::Error::msg(err).context("cannot send update"))
+    }
 
-        let interval = std::time::Duration::from_secs(120);
-        let sleeper = sleep(interval);
-        tokio::pin!(sleeper);
+    /// Prefer the in-memory cursor so that events seen since the last checkpoint are
+    /// not replayed after a reconnect. The stored cursor is rewound to cover events that
+    /// were in flight when it was written.
+    async fn start_cursor(&self, cursor_source: &str, last_time_usec: i64) -> Result<Option<i64>> {
+        if last_time_usec > 0 {
+            return Ok(Some(last_time_usec));
+        }
 
-        let mut time_usec = 0i64;
+        Ok(consumer_control_get(&self.pool, cursor_source)
+            .await?
+            .map(|value| rewind_cursor(value, self.config.cursor_rewind)))
+    }
 
+    /// Reads messages from a connected client until it closes or the task is
+    /// cancelled. Feed reloads and author list changes are applied here
+    /// because they change the jetstream subscription.
+    async fn read(
+        &mut self,
+        client: &mut JetstreamClient,
+        sender: &mpsc::Sender<Message>,
+        mut sleeper: Pin<&mut Sleep>,
+        mut authors_sleeper: Pin<&mut Sleep>,
+    ) -> Disconnect {
         loop {
             tokio::select! {
                 () = self.cancellation_token.cancelled() => {
-                    break;
+                    return Disconnect::Cancelled;
+                },
+                Ok(()) = self.feeds.changed() => {
+                    let feeds = self.feeds.borrow_and_update().clone();
+                    match FeedMatchers::from_config(&feeds) {
+                        Ok(feed_matchers) => {
+                            *self
+                                .feed_matchers
+                                .write()
+                                .unwrap_or_else(PoisonError::into_inner) = feed_matchers;
+                            tracing::info!("consumer feeds reloaded");
+                            if let Err(err) = self.send_options_update(client, None).await {
+                                tracing::error!(error = ?err, "cannot update jetstream options");
+                                return Disconnect::Closed;
+                            }
+                        }
+                        Err(err) => {
+                            tracing::error!(error = ?err, "cannot reload consumer feeds");
+                        }
+                    }
                 },
                 () = &mut sleeper => {
-                        consumer_control_insert(&self.pool, &self.config.jetstream_hostname, time_usec).await?;
-                        sleeper.as_mut().reset(Instant::now() + interval);
+                        self.metrics.log();
+                        sleeper.as_mut().reset(Instant::now() + self.config.checkpoint_interval);
+                },
+                () = &mut authors_sleeper => {
+                        let sources = self
+                            .feed_matchers
+                            .read()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .authors_sources();
+                        let refreshed = match tokio::task::spawn_blocking(move || read_authors(sources)).await {
+                            Ok(refreshed) => refreshed,
+                            Err(err) => {
+                                tracing::error!(error = ?err, "cannot refresh feed authors");
+                                Vec::new()
+                            }
+                        };
+                        let authors_changed = self
+                            .feed_matchers
+                            .write()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .apply_authors(refreshed);
+                        if authors_changed {
+                            if let Err(err) = self.send_options_update(client, None).await {
+                                tracing::error!(error = ?err, "cannot update jetstream options");
+                                return Disconnect::Closed;
+                            }
+                        }
+                        authors_sleeper
+                            .as_mut()
+                            .reset(Instant::now() + self.config.authors_refresh_interval);
                 },
                 item = client.next() => {
-                    if item.is_none() {
-                        tracing::warn!("jetstream connection closed");
-                        break;
-                    }
-                    let item = item.unwrap();
+                    let message = match item {
+                        Some(Ok(message)) => message,
+                        Some(Err(err)) => {
+                            tracing::error!(error = ?err, "error processing jetstream message");
+                            self.metrics.decode_error("websocket");
+                            continue;
+                        }
+                        None => return Disconnect::Closed,
+                    };
 
-                    if let Err(err) = item {
-                        tracing::error!(error = ?err, "error processing jetstream message");
-                        continue;
-                    }
-                    let item = item.unwrap();
+                    self.metrics.messages.inc();
 
-                    if !item.is_binary() {
-                        tracing::warn!("message from jetstream is not binary");
-                        continue;
+                    if send_tracked(sender, message, &self.metrics.stage_backpressure("decode"))
+                        .await
+                        .is_err()
+                    {
+                        return Disconnect::Stopped;
                     }
-                    let payload = item.into_payload();
+                }
+            }
+        }
+    }
+}
 
-                    let decoded = decompressor.decompress(&payload, MAX_MESSAGE_SIZE + 1);
-                    if let Err(err) = decoded {
-                        let length = payload.len();
-                        tracing::error!(error = ?err, length = ?length, "error processing jetstream message");
-                        continue;
-                    }
-                    let decoded = decoded.unwrap();
+impl ConsumerMetrics {
+    fn log(&self) {
+        tracing::info!(
+            messages = ?self.messages.get(),
+            events = ?self.events.get(),
+            feed_content_writes = ?self.feed_content_writes.get(),
+            write_batches = ?self.write_batches.get(),
+            write_retries = ?self.write_retries.get(),
+            decode_backpressure = ?self.stage_backpressure("decode").get(),
+            match_backpressure = ?self.stage_backpressure("match").get(),
+            write_backpressure = ?self.stage_backpressure("write").get(),
+            "consumer pipeline"
+        );
+    }
+}
 
-                    let event = serde_json::from_slice::<model::Event>(&decoded);
-                    if let Err(err) = event {
-                        tracing::error!(error = ?err, "error processing jetstream message");
+/// Sends to the next pipeline stage, counting the sends that had to wait for
+/// room in the channel. An error means the next stage has stopped.
+async fn send_tracked<T>(sender: &mpsc::Sender<T>, value: T, backpressure: &Counter) -> Result<()> {
+    let value = match sender.try_send(value) {
+        Ok(()) => return Ok(()),
+        Err(mpsc::error::TrySendError::Full(value)) => {
+            backpressure.inc();
+            value
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => {
+            return Err(anyhow!("pipeline stage stopped"));
+        }
+    };
 
-                        #[cfg(debug_assertions)]
-                        {
-                            println!("{:?}", std::str::from_utf8(&decoded));
-                        }
+    sender
+        .send(value)
+        .await
+        .map_err(|_| anyhow!("pipeline stage stopped"))
+}
 
-                        continue;
-                    }
-                    let event = event.unwrap();
+/// Decompresses and parses messages, passing them to the archive stage when
+/// one is running.
+async fn decode_stage(
+    mut decompressor: zstd::bulk::Decompressor<'_>,
+    mut archive: Option<mpsc::Sender<Vec<u8>>>,
+    mut receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<model::Event>,
+    time_usec: Arc<AtomicI64>,
+    metrics: ConsumerMetrics,
+) -> Result<()> {
+    while let Some(message) = receiver.recv().await {
+        let Some(decoded) = decompress_message(&mut decompressor, message, &metrics) else {
+            continue;
+        };
 
-                    time_usec = std::cmp::max(time_usec, event.time_us);
+        if let Some(archive_sender) = archive.as_ref() {
+            if send_tracked(
+                archive_sender,
+                decoded.clone(),
+                &metrics.stage_backpressure("archive"),
+            )
+            .await
+            .is_err()
+            {
+                tracing::error!("archive stage stopped, events are no longer archived");
+                archive = None;
+            }
+        }
 
-                    if event.clone().kind != "commit" {
-                        continue;
-                    }
+        let Some(event) = parse_event(&decoded, &metrics) else {
+            continue;
+        };
 
-                    let event_value = serde_json::to_value(event.clone());
-                    if let Err(err) = event_value {
-                        tracing::error!(error = ?err, "error processing jetstream message");
-                        continue;
+        metrics.events.inc();
+        metrics
+            .lag_seconds
+            .set((chrono::Utc::now().timestamp_micros() - event.time_us) as f64 / 1_000_000.0);
+        time_usec.fetch_max(event.time_us, Ordering::Relaxed);
+
+        if send_tracked(&sender, event, &metrics.stage_backpressure("match"))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes decoded messages to the archive. Runs on a blocking thread so that
+/// compression and file writes do not stall the runtime. The archive is
+/// flushed at most once per `flush_interval` and finished when the channel
+/// closes.
+fn archive_stage(
+    mut archive: ArchiveWriter,
+    flush_interval: Duration,
+    mut receiver: mpsc::Receiver<Vec<u8>>,
+) -> Result<()> {
+    let mut flushed_at = std::time::Instant::now();
+
+    while let Some(decoded) = receiver.blocking_recv() {
+        if let Err(err) = archive.write(&decoded) {
+            tracing::error!(error = ?err, "cannot write archive");
+        }
+
+        if flushed_at.elapsed() >= flush_interval {
+            if let Err(err) = archive.flush() {
+                tracing::error!(error = ?err, "cannot flush archive");
+            }
+            flushed_at = std::time::Instant::now();
+        }
+    }
+
+    archive.finish()
+}
+
+/// Reads events within the window from an archive and sends them on until the
+/// archive ends or the receiver is dropped.
+fn read_archive(
+    archive: Box<dyn BufRead + Send>,
+    path: &str,
+    window: &Range<i64>,
+    sender: mpsc::Sender<model::Event>,
+) {
+    for line in archive.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::warn!(error = ?err, path = ?path, "archive ended unexpectedly");
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event = match serde_json::from_str::<model::Event>(&line) {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::error!(error = ?err, "error processing archived event");
+                continue;
+            }
+        };
+
+        if !window.contains(&event.time_us) {
+            continue;
+        }
+
+        if sender.blocking_send(event).is_err() {
+            break;
+        }
+    }
+}
+
+/// Matches events against feeds on up to `workers` tasks at once. Results are
+/// passed on in the order events were received so that changes to the same
+/// record are applied in order.
+async fn match_stage(
+    feed_matchers: Arc<RwLock<FeedMatchers>>,
+    workers: usize,
+    mut receiver: mpsc::Receiver<model::Event>,
+    sender: mpsc::Sender<MatchedEvent>,
+    metrics: ConsumerMetrics,
+) -> Result<()> {
+    let mut matched = futures_util::stream::poll_fn(|cx| receiver.poll_recv(cx))
+        .map(|event| {
+            let feed_matchers = feed_matchers.clone();
+            tokio::spawn(async move {
+                let mut writes = Vec::new();
+                match_event(
+                    &feed_matchers.read().unwrap_or_else(PoisonError::into_inner),
+                    &event,
+                    &mut writes,
+                );
+                MatchedEvent {
+                    time_us: event.time_us,
+                    writes,
+                }
+            })
+        })
+        .buffered(workers);
+
+    while let Some(result) = matched.next().await {
+        let matched_event = match result {
+            Ok(matched_event) => matched_event,
+            Err(err) => {
+                tracing::error!(error = ?err, "error matching jetstream event");
+                continue;
+            }
+        };
+
+        for write in &matched_event.writes {
+            if let FeedContentWrite::Insert(feed_content) | FeedContentWrite::Upsert(feed_content) =
+                write
+            {
+                metrics
+                    .feed_matches
+                    .get_or_create(&FeedLabels {
+                        feed: feed_content.feed_id.clone(),
+                    })
+                    .inc();
+            }
+        }
+
+        if send_tracked(&sender, matched_event, &metrics.stage_backpressure("write"))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects feed content changes and writes them with the cursor in one
+/// transaction when the batch is full or has waited long enough. The cursor
+/// is also written every checkpoint interval when no changes are queued.
+async fn write_stage(
+    pool: StoragePool,
+    cursor_source: String,
+    config: ConsumerTaskConfig,
+    mut receiver: mpsc::Receiver<MatchedEvent>,
+    cancellation_token: CancellationToken,
+    metrics: ConsumerMetrics,
+) -> Result<()> {
+    let sleeper = sleep(config.checkpoint_interval);
+    tokio::pin!(sleeper);
+
+    let mut writes = Vec::new();
+    let mut time_usec = 0i64;
+    let mut written_time_usec = 0i64;
+    let mut batch_deadline = Instant::now();
+
+    loop {
+        tokio::select! {
+            matched_event = receiver.recv() => {
+                let Some(matched_event) = matched_event else {
+                    break;
+                };
+
+                if writes.is_empty() {
+                    batch_deadline = Instant::now() + config.batch_interval;
+                }
+
+                time_usec = std::cmp::max(time_usec, matched_event.time_us);
+                writes.extend(matched_event.writes);
+
+                if writes.len() < config.batch_size {
+                    continue;
+                }
+            },
+            () = sleep_until(batch_deadline), if !writes.is_empty() => { },
+            () = &mut sleeper => {
+                sleeper.as_mut().reset(Instant::now() + config.checkpoint_interval);
+                if time_usec == written_time_usec {
+                    continue;
+                }
+            },
+        }
+
+        write_with_retry(
+            &pool,
+            &mut writes,
+            (&cursor_source, time_usec),
+            &cancellation_token,
+            &metrics,
+        )
+        .await?;
+        written_time_usec = time_usec;
+    }
+
+    if !writes.is_empty() || time_usec > written_time_usec {
+        write_with_retry(
+            &pool,
+            &mut writes,
+            (&cursor_source, time_usec),
+            &cancellation_token,
+            &metrics,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Writes a batch, retrying with backoff until it succeeds so that a
+/// transient storage error does not stop the consumer. Once the task is
+/// cancelled the batch is attempted one more time before giving up.
+async fn write_with_retry(
+    pool: &StoragePool,
+    writes: &mut Vec<FeedContentWrite>,
+    cursor: (&str, i64),
+    cancellation_token: &CancellationToken,
+    metrics: &ConsumerMetrics,
+) -> Result<()> {
+    let mut backoff = WRITE_RETRY_BACKOFF_MIN;
+
+    loop {
+        match feed_content_write_batch(pool, writes, Some(cursor)).await {
+            Ok(()) => {
+                metrics.write_batches.inc();
+                metrics.feed_content_writes.inc_by(writes.len() as u64);
+                writes.clear();
+                return Ok(());
+            }
+            Err(err) => {
+                if cancellation_token.is_cancelled() {
+                    return Err(err);
+                }
+                metrics.write_retries.inc();
+                tracing::error!(error = ?err, delay = ?backoff, "cannot write feed content, retrying");
+            }
+        }
+
+        tokio::select! {
+            () = cancellation_token.cancelled() => { },
+            () = sleep(jitter(backoff)) => { },
+        }
+        backoff = std::cmp::min(backoff * 2, WRITE_RETRY_BACKOFF_MAX);
+    }
+}
+
+/// Matches an event against every feed and queues the resulting changes to
+/// feed content. This is shared by the websocket consumer and replay.
+fn match_event(
+    feed_matchers: &FeedMatchers,
+    event: &model::Event,
+    writes: &mut Vec<FeedContentWrite>,
+) {
+    if event.kind != "commit" {
+        return;
+    }
+
+    let event_value = serde_json::to_value(event);
+    if let Err(err) = event_value {
+        tracing::error!(error = ?err, "error processing jetstream message");
+        return;
+    }
+    let event_value = event_value.unwrap();
+
+    let collection = match &event.commit {
+        Some(commit) => commit.collection(),
+        None => return,
+    };
+
+    let feed_matchers = feed_matchers.0.iter().filter(|feed_matcher| {
+        feed_matcher.wants_collection(collection) && feed_matcher.wants_author(&event.did)
+    });
+
+    match &event.commit {
+        Some(model::CommitOp::Create { .. }) => {
+            for feed_matcher in feed_matchers {
+                if feed_matcher.matches(&event_value) {
+                    tracing::debug!(feed_id = ?feed_matcher.feed, "matched event");
+                    if let Some((uri, cid)) = model::to_strong_ref(event) {
+                        writes.push(FeedContentWrite::Insert(FeedContent {
+                            feed_id: feed_matcher.feed.clone(),
+                            uri,
+                            indexed_at: event.time_us,
+                            cid,
+                        }));
                     }
-                    let event_value = event_value.unwrap();
-
-                    for feed_matcher in self.feed_matchers.0.iter() {
-                        if feed_matcher.matches(&event_value) {
-                            tracing::debug!(feed_id = ?feed_matcher.feed, "matched event");
-                            if let Some((uri, cid)) = model::to_post_strong_ref(&event) {
-                                let feed_content = storage::model::FeedContent{
-                                    feed_id: feed_matcher.feed.clone(),
-                                    uri,
-                                    indexed_at: event.clone().time_us,
-                                    cid,
-                                };
-                                feed_content_insert(&self.pool, &feed_content).await?;
-                            }
-                        }
+                }
+            }
+        }
+        Some(model::CommitOp::Update { .. }) => {
+            if let Some((uri, cid)) = model::to_strong_ref(event) {
+                // Records such as likes and reposts point at a subject that may
+                // have been matched on its own, so only the record itself is removed.
+                let is_record_uri = model::to_aturi(event).as_ref() == Some(&uri);
+
+                for feed_matcher in feed_matchers {
+                    if feed_matcher.matches(&event_value) {
+                        tracing::debug!(feed_id = ?feed_matcher.feed, "matched updated event");
+                        writes.push(FeedContentWrite::Upsert(FeedContent {
+                            feed_id: feed_matcher.feed.clone(),
+                            uri: uri.clone(),
+                            indexed_at: event.time_us,
+                            cid: cid.clone(),
+                        }));
+                    } else if is_record_uri {
+                        writes.push(FeedContentWrite::Delete {
+                            feed_id: feed_matcher.feed.clone(),
+                            uri: uri.clone(),
+                        });
                     }
                 }
             }
         }
+        Some(model::CommitOp::Delete { .. }) => {
+            if let Some(uri) = model::to_aturi(event) {
+                writes.push(FeedContentWrite::DeleteUri(uri));
+            }
+        }
+        None => {}
+    }
+}
 
-        tracing::debug!("ConsumerTask stopped");
+fn decompress_message(
+    decompressor: &mut zstd::bulk::Decompressor<'_>,
+    item: Message,
+    metrics: &ConsumerMetrics,
+) -> Option<Vec<u8>> {
+    if !item.is_binary() {
+        tracing::warn!("message from jetstream is not binary");
+        metrics.decode_error("not_binary");
+        return None;
+    }
+    let payload = item.into_payload();
 
-        Ok(())
+    let decoded = decompressor.decompress(&payload, MAX_MESSAGE_SIZE + 1);
+    if let Err(err) = decoded {
+        let length = payload.len();
+        tracing::error!(error = ?err, length = ?length, "error processing jetstream message");
+        metrics.decode_error("decompress");
+        return None;
+    }
+    decoded.ok()
+}
+
+fn parse_event(decoded: &[u8], metrics: &ConsumerMetrics) -> Option<model::Event> {
+    let event = serde_json::from_slice::<model::Event>(decoded);
+    if let Err(err) = event {
+        tracing::error!(error = ?err, "error processing jetstream message");
+        metrics.decode_error("parse");
+
+        #[cfg(debug_assertions)]
+        {
+            println!("{:?}", std::str::from_utf8(decoded));
+        }
+
+        return None;
+    }
+    event.ok()
+}
+
+/// Moves a stored cursor back by the rewind duration, stopping at zero.
+fn rewind_cursor(cursor: i64, rewind: Duration) -> i64 {
+    let rewind = i64::try_from(rewind.as_micros()).unwrap_or(i64::MAX);
+    cursor.saturating_sub(rewind).max(0)
+}
+
+/// The hostname and backoff used for the next jetstream connection attempt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Reconnect {
+    hostname_index: usize,
+    hostname_failures: u32,
+    backoff: Duration,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            hostname_index: 0,
+            hostname_failures: 0,
+            backoff: RECONNECT_BACKOFF_MIN,
+        }
+    }
+}
+
+impl Reconnect {
+    /// Returns the state for the next attempt and the delay, before jitter, to
+    /// wait before making it. A connection that received events resets the
+    /// backoff. Otherwise the backoff doubles, and after
+    /// `MAX_HOSTNAME_FAILURES` failures the next of `hostnames` is used.
+    fn next(self, progressed: bool, hostnames: usize) -> (Self, Duration) {
+        if progressed {
+            let next = Self {
+                hostname_index: self.hostname_index,
+                ..Self::default()
+            };
+            return (next, next.backoff);
+        }
+
+        let mut next = Self {
+            hostname_failures: self.hostname_failures + 1,
+            backoff: std::cmp::min(self.backoff * 2, RECONNECT_BACKOFF_MAX),
+            ..self
+        };
+        if next.hostname_failures >= MAX_HOSTNAME_FAILURES && hostnames > 1 {
+            next.hostname_failures = 0;
+            next.hostname_index = (self.hostname_index + 1) % hostnames;
+        }
+        (next, self.backoff)
     }
 }
 
+/// Returns a delay between half and all of the given delay so that many
+/// consumers do not reconnect in lockstep.
+fn jitter(delay: Duration) -> Duration {
+    let half = delay / 2;
+    half + half.mul_f64(rand::random::<f64>())
+}
+
 pub(crate) mod model {
 
     use std::collections::HashMap;
@@ -217,6 +1118,9 @@ pub(crate) mod model {
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub(crate) struct StrongRef {
         pub(crate) uri: String,
+
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        pub(crate) cid: Option<String>,
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -240,6 +1144,22 @@ pub(crate) mod model {
             extra: HashMap<String, serde_json::Value>,
         },
 
+        #[serde(rename = "app.bsky.feed.repost")]
+        Repost {
+            subject: StrongRef,
+
+            #[serde(flatten)]
+            extra: HashMap<String, serde_json::Value>,
+        },
+
+        #[serde(rename = "app.bsky.feed.like")]
+        Like {
+            subject: StrongRef,
+
+            #[serde(flatten)]
+            extra: HashMap<String, serde_json::Value>,
+        },
+
         #[serde(untagged)]
         Other {
             #[serde(flatten)]
@@ -282,17 +1202,411 @@ pub(crate) mod model {
         pub(crate) commit: Option<CommitOp>,
     }
 
-    pub(crate) fn to_post_strong_ref(event: &Event) -> Option<(String, String)> {
-        if let Some(CommitOp::Create {
-            collection,
-            rkey,
-            cid,
-            ..
-        }) = &event.commit
-        {
-            let uri = format!("at://{}/{}/{}", event.did, collection, rkey);
-            return Some((uri, cid.clone()));
+    impl CommitOp {
+        pub(crate) fn collection(&self) -> &str {
+            match self {
+                CommitOp::Create { collection, .. }
+                | CommitOp::Update { collection, .. }
+                | CommitOp::Delete { collection, .. } => collection,
+            }
+        }
+    }
+
+    /// Returns the strong ref that should be stored for an event. Reposts and
+    /// likes refer to their subject post, all other records refer to themselves.
+    pub(crate) fn to_strong_ref(event: &Event) -> Option<(String, String)> {
+        match &event.commit {
+            Some(CommitOp::Create {
+                collection,
+                rkey,
+                record,
+                cid,
+                ..
+            })
+            | Some(CommitOp::Update {
+                collection,
+                rkey,
+                record,
+                cid,
+                ..
+            }) => match record {
+                Record::Repost { subject, .. } | Record::Like { subject, .. } => subject
+                    .cid
+                    .as_ref()
+                    .map(|subject_cid| (subject.uri.clone(), subject_cid.clone())),
+                _ => {
+                    let uri = format!("at://{}/{}/{}", event.did, collection, rkey);
+                    Some((uri, cid.clone()))
+                }
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_aturi(event: &Event) -> Option<String> {
+        match &event.commit {
+            Some(CommitOp::Create {
+                collection, rkey, ..
+            })
+            | Some(CommitOp::Update {
+                collection, rkey, ..
+            })
+            | Some(CommitOp::Delete {
+                collection, rkey, ..
+            }) => Some(format!("at://{}/{}/{}", event.did, collection, rkey)),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+    use tokio::sync::{mpsc, watch};
+    use tokio_util::sync::CancellationToken;
+
+    use std::sync::{Arc, RwLock};
+
+    use super::model;
+    use super::{
+        match_stage, rewind_cursor, write_stage, ConsumerTask, ConsumerTaskConfig, MatchedEvent,
+        Reconnect, RECONNECT_BACKOFF_MAX, RECONNECT_BACKOFF_MIN,
+    };
+    use crate::config;
+    use crate::matcher::FeedMatchers;
+    use crate::metrics::ConsumerMetrics;
+    use crate::storage::model::{FeedContent, FeedContentWrite};
+    use crate::storage::{consumer_control_get, feed_content_paginate};
+
+    const CONFIG_YAML: &str = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/dnd"
+  name: "DnD"
+  description: "DnD"
+  matchers:
+  - path: "$.commit.record.text"
+    values: ["dnd", "question"]
+    type: sequence
+"#;
+
+    const EVENTS: &str = r#"{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829414,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"create","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2u","record":{"$type":"app.bsky.feed.post","createdAt":"2024-11-01T19:58:12.980Z","text":"hey dnd question"},"cid":"bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"}}
+{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829415,"kind":"commit","commit":{"rev":"3l7vxhiuibq2v","operation":"create","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2v","record":{"$type":"app.bsky.feed.post","createdAt":"2024-11-01T19:58:12.980Z","text":"another dnd question"},"cid":"bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74"}}
+{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829416,"kind":"commit","commit":{"rev":"3l7vxhiuibq2w","operation":"create","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2w","record":{"$type":"app.bsky.feed.post","createdAt":"2024-11-01T19:58:12.980Z","text":"unrelated"},"cid":"bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74"}}
+{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829417,"kind":"commit","commit":{"rev":"3l7vxhiuibq2x","operation":"delete","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2v"}}
+"#;
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn replay_archive(pool: SqlitePool) -> sqlx::Result<()> {
+        let pool = crate::storage::StoragePool::from(pool);
+        let path =
+            std::env::temp_dir().join(format!("supercell-replay-{}.jsonl", std::process::id()));
+        std::fs::write(&path, EVENTS).expect("archive is written");
+
+        let config_feeds: config::Feeds =
+            serde_yaml::from_str(CONFIG_YAML).expect("config is valid");
+        let (_feeds_sender, feeds_receiver) = watch::channel(config_feeds);
+
+        let task = ConsumerTask::new(
+            pool.clone(),
+            ConsumerTaskConfig {
+                user_agent: "supercell".to_string(),
+                zstd_dictionary_location: "".to_string(),
+                jetstream_hostnames: vec!["localhost".to_string()],
+                checkpoint_interval: std::time::Duration::from_secs(120),
+                authors_refresh_interval: std::time::Duration::from_secs(120),
+                cursor_rewind: std::time::Duration::ZERO,
+                archive_directory: None,
+                batch_size: 2,
+                batch_interval: std::time::Duration::from_secs(1),
+            },
+            feeds_receiver,
+            ConsumerMetrics::default(),
+            CancellationToken::new(),
+        )
+        .expect("consumer task is created");
+
+        let count = task
+            .replay(path.to_str().expect("path is valid"), &(i64::MIN..i64::MAX))
+            .await
+            .expect("archive is replayed");
+        assert_eq!(count, 4);
+
+        std::fs::remove_file(&path).expect("archive is removed");
+
+        let records = feed_content_paginate(
+            &pool,
+            "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/dnd",
+            None,
+            None,
+        )
+        .await
+        .expect("failed to paginate records");
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].uri,
+            "at://did:plc:tgudj2fjm77pzkuawquqhsxm/app.bsky.feed.post/3l7vxhiu4kq2u"
+        );
+        assert_eq!(records[0].indexed_at, 1730491093829414);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn match_and_write_stages(pool: SqlitePool) -> sqlx::Result<()> {
+        let pool = crate::storage::StoragePool::from(pool);
+
+        let config_feeds: config::Feeds =
+            serde_yaml::from_str(CONFIG_YAML).expect("config is valid");
+        let feed_matchers = FeedMatchers::from_config(&config_feeds).expect("matchers are valid");
+
+        let config = ConsumerTaskConfig {
+            user_agent: "supercell".to_string(),
+            zstd_dictionary_location: "".to_string(),
+            jetstream_hostnames: vec!["localhost".to_string()],
+            checkpoint_interval: std::time::Duration::from_secs(120),
+            authors_refresh_interval: std::time::Duration::from_secs(120),
+            cursor_rewind: std::time::Duration::ZERO,
+            archive_directory: None,
+            batch_size: 100,
+            batch_interval: std::time::Duration::from_secs(1),
+        };
+
+        let metrics = ConsumerMetrics::default();
+        let (event_sender, event_receiver) = mpsc::channel(1);
+        let (matched_sender, matched_receiver) = mpsc::channel(1);
+
+        let send_events = async move {
+            for line in EVENTS.lines() {
+                let event: model::Event = serde_json::from_str(line).expect("json is valid");
+                event_sender.send(event).await.expect("event is sent");
+            }
+        };
+
+        let (_, match_result, write_result) = tokio::join!(
+            send_events,
+            match_stage(
+                Arc::new(RwLock::new(feed_matchers)),
+                4,
+                event_receiver,
+                matched_sender,
+                metrics.clone(),
+            ),
+            write_stage(
+                pool.clone(),
+                "localhost".to_string(),
+                config,
+                matched_receiver,
+                CancellationToken::new(),
+                metrics.clone(),
+            ),
+        );
+        match_result.expect("match stage completes");
+        write_result.expect("write stage completes");
+
+        let records = feed_content_paginate(
+            &pool,
+            "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/dnd",
+            None,
+            None,
+        )
+        .await
+        .expect("failed to paginate records");
+
+        // The delete of the second post is applied after its create.
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].uri,
+            "at://did:plc:tgudj2fjm77pzkuawquqhsxm/app.bsky.feed.post/3l7vxhiu4kq2u"
+        );
+        assert_eq!(
+            consumer_control_get(&pool, "localhost")
+                .await
+                .expect("failed to get cursor"),
+            Some(1730491093829417)
+        );
+        assert_eq!(metrics.write_batches.get(), 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn write_stage_flushes_on_cancel(pool: SqlitePool) -> sqlx::Result<()> {
+        let pool = crate::storage::StoragePool::from(pool);
+
+        let config = ConsumerTaskConfig {
+            user_agent: "supercell".to_string(),
+            zstd_dictionary_location: "".to_string(),
+            jetstream_hostnames: vec!["localhost".to_string()],
+            checkpoint_interval: std::time::Duration::from_secs(120),
+            authors_refresh_interval: std::time::Duration::from_secs(120),
+            cursor_rewind: std::time::Duration::ZERO,
+            archive_directory: None,
+            batch_size: 2,
+            batch_interval: std::time::Duration::from_secs(120),
+        };
+
+        let feed_id = "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/dnd";
+        let metrics = ConsumerMetrics::default();
+        let (matched_sender, matched_receiver) = mpsc::channel(4);
+        // The last event is replayed after a reconnect with a rewound cursor, so
+        // its time is older than the cursor already written with the first two.
+        for (time_us, rkey) in [
+            (1730491093829414, "first"),
+            (1730491093829415, "second"),
+            (1730491093829413, "rewound"),
+        ] {
+            let writes = vec![FeedContentWrite::Insert(FeedContent {
+                feed_id: feed_id.to_string(),
+                uri: format!(
+                    "at://did:plc:tgudj2fjm77pzkuawquqhsxm/app.bsky.feed.post/{}",
+                    rkey
+                ),
+                indexed_at: time_us,
+                cid: "bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4".to_string(),
+            })];
+            matched_sender
+                .send(MatchedEvent { time_us, writes })
+                .await
+                .expect("event is sent");
+        }
+
+        // Shutdown cancels the token and the match stage closes its channel
+        // while writes are still queued and neither batch limit was reached.
+        let cancellation_token = CancellationToken::new();
+        cancellation_token.cancel();
+        drop(matched_sender);
+
+        write_stage(
+            pool.clone(),
+            "localhost".to_string(),
+            config,
+            matched_receiver,
+            cancellation_token,
+            metrics.clone(),
+        )
+        .await
+        .expect("write stage completes");
+
+        let records = feed_content_paginate(&pool, feed_id, None, None)
+            .await
+            .expect("failed to paginate records");
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            consumer_control_get(&pool, "localhost")
+                .await
+                .expect("failed to get cursor"),
+            Some(1730491093829415)
+        );
+        assert_eq!(metrics.write_batches.get(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cursor_rewind() {
+        let secs = std::time::Duration::from_secs;
+
+        assert_eq!(rewind_cursor(1730491093829414, secs(0)), 1730491093829414);
+        assert_eq!(rewind_cursor(1730491093829414, secs(5)), 1730491088829414);
+
+        // Rewinding past the start of time stops at zero.
+        assert_eq!(rewind_cursor(4_000_000, secs(5)), 0);
+        assert_eq!(rewind_cursor(1730491093829414, std::time::Duration::MAX), 0);
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        let secs = std::time::Duration::from_secs;
+
+        // Failures double the backoff up to the maximum and rotate through the
+        // hostnames, wrapping around to the first.
+        let mut reconnect = Reconnect::default();
+        let mut attempts = Vec::new();
+        for _ in 0..10 {
+            let (next, delay) = reconnect.next(false, 2);
+            attempts.push((reconnect.hostname_index, delay));
+            reconnect = next;
+        }
+        assert_eq!(
+            attempts,
+            vec![
+                (0, secs(1)),
+                (0, secs(2)),
+                (0, secs(4)),
+                (1, secs(8)),
+                (1, secs(16)),
+                (1, secs(32)),
+                (0, secs(64)),
+                (0, secs(128)),
+                (0, secs(256)),
+                (1, RECONNECT_BACKOFF_MAX),
+            ]
+        );
+        assert_eq!(reconnect.backoff, RECONNECT_BACKOFF_MAX);
+
+        // Receiving events resets the backoff but keeps the hostname.
+        let (reconnect, delay) = reconnect.next(true, 2);
+        assert_eq!(delay, RECONNECT_BACKOFF_MIN);
+        assert_eq!(
+            reconnect,
+            Reconnect {
+                hostname_index: 1,
+                ..Reconnect::default()
+            }
+        );
+
+        // A single hostname is never rotated away from.
+        let mut reconnect = Reconnect::default();
+        for _ in 0..10 {
+            reconnect = reconnect.next(false, 1).0;
+            assert_eq!(reconnect.hostname_index, 0);
+        }
+    }
+
+    #[test]
+    fn strong_ref() {
+        let tests = vec![
+            (
+                r#"{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829414,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"create","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2u","record":{"$type":"app.bsky.feed.post","createdAt":"2024-11-01T19:58:12.980Z","text":"hello"},"cid":"bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"}}"#,
+                Some((
+                    "at://did:plc:tgudj2fjm77pzkuawquqhsxm/app.bsky.feed.post/3l7vxhiu4kq2u",
+                    "bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4",
+                )),
+            ),
+            (
+                r#"{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829414,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"create","collection":"app.bsky.feed.repost","rkey":"3l7vxhiu4kq2u","record":{"$type":"app.bsky.feed.repost","createdAt":"2024-11-01T19:58:12.980Z","subject":{"uri":"at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/3la3bqjg4hx2n","cid":"bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74"}},"cid":"bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"}}"#,
+                Some((
+                    "at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/3la3bqjg4hx2n",
+                    "bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74",
+                )),
+            ),
+            (
+                r#"{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829414,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"create","collection":"app.bsky.feed.like","rkey":"3l7vxhiu4kq2u","record":{"$type":"app.bsky.feed.like","createdAt":"2024-11-01T19:58:12.980Z","subject":{"uri":"at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/3la3bqjg4hx2n","cid":"bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74"}},"cid":"bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"}}"#,
+                Some((
+                    "at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/3la3bqjg4hx2n",
+                    "bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74",
+                )),
+            ),
+            (
+                r#"{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829414,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"create","collection":"events.smokesignal.calendar.event","rkey":"3l7vxhiu4kq2u","record":{"$type":"events.smokesignal.calendar.event","name":"Meetup"},"cid":"bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"}}"#,
+                Some((
+                    "at://did:plc:tgudj2fjm77pzkuawquqhsxm/events.smokesignal.calendar.event/3l7vxhiu4kq2u",
+                    "bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4",
+                )),
+            ),
+            (
+                r#"{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829414,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"delete","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2u"}}"#,
+                None,
+            ),
+        ];
+
+        for (raw_json, expected) in tests {
+            let event: model::Event = serde_json::from_str(raw_json).expect("json is valid");
+            assert_eq!(
+                model::to_strong_ref(&event),
+                expected.map(|(uri, cid)| (uri.to_string(), cid.to_string()))
+            );
         }
-        None
     }
 }
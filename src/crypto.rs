@@ -16,28 +16,137 @@ pub struct JwtHeader {
     pub alg: String,
 }
 
-pub(crate) fn validate(multibase_key: &str, signature: &[u8], content: &str) -> Result<()> {
+/// Verifies a compact `r || s` signature of `content` made with the JWT
+/// algorithm `alg`, which must match the type of the multibase key. Signatures
+/// with a high S value are normalized before verifying, matching the
+/// `allowMalleableSig` option of the atproto reference service auth verifier.
+pub(crate) fn validate(
+    multibase_key: &str,
+    alg: &str,
+    signature: &[u8],
+    content: &str,
+) -> Result<()> {
     let (_, decoded_multibase_key) = multibase::decode(multibase_key)?;
-    match &decoded_multibase_key[..2] {
+    let key_type = decoded_multibase_key
+        .get(..2)
+        .ok_or(anyhow!("invalid multibase: too short"))?;
+    match (alg, key_type) {
         // secp256k1
-        [0xe7, 0x01] => {
-            let signature = ecdsa::Signature::from_slice(signature)?;
+        ("ES256K", [0xe7, 0x01]) => {
+            let signature = k256::ecdsa::Signature::from_slice(signature)?;
+            let signature = signature.normalize_s().unwrap_or(signature);
             let verifying_key =
                 k256::ecdsa::VerifyingKey::from_sec1_bytes(&decoded_multibase_key[2..])?;
             ecdsa::signature::Verifier::verify(&verifying_key, content.as_bytes(), &signature)?;
             Ok(())
         }
         // p256
-        [0x80, 0x24] => {
-            let signature = ecdsa::Signature::from_slice(signature)?;
+        ("ES256", [0x80, 0x24]) => {
+            let signature = p256::ecdsa::Signature::from_slice(signature)?;
+            let signature = signature.normalize_s().unwrap_or(signature);
             let verifying_key =
                 p256::ecdsa::VerifyingKey::from_sec1_bytes(&decoded_multibase_key[2..])?;
             ecdsa::signature::Verifier::verify(&verifying_key, content.as_bytes(), &signature)?;
             Ok(())
         }
-        _ => Err(anyhow!(
-            "invalid multibase: {:?}",
-            &decoded_multibase_key[..2]
-        )),
+        (_, [0xe7, 0x01]) | (_, [0x80, 0x24]) => {
+            Err(anyhow!("algorithm {} does not match key type", alg))
+        }
+        _ => Err(anyhow!("invalid multibase: {:?}", key_type)),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use ecdsa::signature::Signer;
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    pub(crate) type Sign = Box<dyn Fn(&[u8]) -> Vec<u8>>;
+
+    /// Generates a signing key for `alg`, returning it with its public key as
+    /// a multibase multikey.
+    pub(crate) fn generate_key(alg: &str) -> (Sign, String) {
+        match alg {
+            "ES256K" => {
+                let signing_key = k256::ecdsa::SigningKey::random(&mut OsRng);
+                let public_key = signing_key
+                    .verifying_key()
+                    .to_encoded_point(true)
+                    .as_bytes()
+                    .to_vec();
+                let sign = move |content: &[u8]| {
+                    let signature: k256::ecdsa::Signature = signing_key.sign(content);
+                    signature
+                        .normalize_s()
+                        .unwrap_or(signature)
+                        .to_bytes()
+                        .to_vec()
+                };
+                (Box::new(sign), multikey(&[0xe7, 0x01], &public_key))
+            }
+            "ES256" => {
+                let signing_key = p256::ecdsa::SigningKey::random(&mut OsRng);
+                let public_key = signing_key
+                    .verifying_key()
+                    .to_encoded_point(true)
+                    .as_bytes()
+                    .to_vec();
+                let sign = move |content: &[u8]| {
+                    let signature: p256::ecdsa::Signature = signing_key.sign(content);
+                    signature
+                        .normalize_s()
+                        .unwrap_or(signature)
+                        .to_bytes()
+                        .to_vec()
+                };
+                (Box::new(sign), multikey(&[0x80, 0x24], &public_key))
+            }
+            _ => panic!("unsupported algorithm {}", alg),
+        }
+    }
+
+    fn multikey(prefix: &[u8], public_key: &[u8]) -> String {
+        multibase::encode(multibase::Base::Base58Btc, [prefix, public_key].concat())
+    }
+
+    #[test]
+    fn validate_both_curves() {
+        for alg in ["ES256K", "ES256"] {
+            let (sign, key) = generate_key(alg);
+            let signature = sign(b"header.claims");
+
+            assert!(validate(&key, alg, &signature, "header.claims").is_ok());
+            assert!(validate(&key, alg, &signature, "header.other").is_err());
+        }
+    }
+
+    #[test]
+    fn validate_algorithm_key_mismatch() {
+        let (sign, key) = generate_key("ES256K");
+        let signature = sign(b"header.claims");
+        assert!(validate(&key, "ES256", &signature, "header.claims").is_err());
+
+        let (sign, key) = generate_key("ES256");
+        let signature = sign(b"header.claims");
+        assert!(validate(&key, "ES256K", &signature, "header.claims").is_err());
+    }
+
+    #[test]
+    fn validate_accepts_high_s() {
+        let (sign, key) = generate_key("ES256K");
+        let signature = k256::ecdsa::Signature::from_slice(&sign(b"header.claims")).unwrap();
+        let (r, s) = signature.split_scalars();
+        let high_s = k256::ecdsa::Signature::from_scalars(r, -*s).unwrap();
+        assert!(validate(&key, "ES256K", &high_s.to_bytes(), "header.claims").is_ok());
+        assert!(validate(&key, "ES256K", &high_s.to_bytes(), "header.other").is_err());
+
+        let (sign, key) = generate_key("ES256");
+        let signature = p256::ecdsa::Signature::from_slice(&sign(b"header.claims")).unwrap();
+        let (r, s) = signature.split_scalars();
+        let high_s = p256::ecdsa::Signature::from_scalars(r, -*s).unwrap();
+        assert!(validate(&key, "ES256", &high_s.to_bytes(), "header.claims").is_ok());
+        assert!(validate(&key, "ES256", &high_s.to_bytes(), "header.other").is_err());
     }
 }
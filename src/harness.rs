@@ -0,0 +1,158 @@
+use std::io::BufRead;
+
+use anyhow::{Context, Result};
+
+use crate::config;
+use crate::consumer::model;
+use crate::matcher::FeedMatchers;
+
+pub struct EventMatch {
+    pub line: usize,
+    pub uri: Option<String>,
+    pub matcher: usize,
+    pub matcher_kind: &'static str,
+}
+
+pub struct FeedReport {
+    pub feed: String,
+    pub matches: Vec<EventMatch>,
+}
+
+pub struct HarnessReport {
+    pub events: usize,
+    pub errors: Vec<(usize, String)>,
+    pub feeds: Vec<FeedReport>,
+}
+
+/// Runs the configured feed matchers over recorded jetstream events, one JSON
+/// event per line, without connecting to jetstream or the database.
+pub fn test_matchers(config_feeds: &config::Feeds, events: impl BufRead) -> Result<HarnessReport> {
+    let feed_matchers = FeedMatchers::from_config(config_feeds)?;
+
+    let mut report = HarnessReport {
+        events: 0,
+        errors: vec![],
+        feeds: config_feeds
+            .feeds
+            .iter()
+            .map(|config_feed| FeedReport {
+                feed: config_feed.uri.clone(),
+                matches: vec![],
+            })
+            .collect(),
+    };
+
+    for (index, line) in events.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.context("cannot read events")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event = match serde_json::from_str::<model::Event>(&line) {
+            Ok(event) => event,
+            Err(err) => {
+                report.errors.push((line_number, err.to_string()));
+                continue;
+            }
+        };
+        report.events += 1;
+
+        // Deletes remove content by URI and are never matched, as in the consumer.
+        let Some(commit) = &event.commit else {
+            continue;
+        };
+        if event.kind != "commit" || matches!(commit, model::CommitOp::Delete { .. }) {
+            continue;
+        }
+
+        let event_value = serde_json::to_value(&event)?;
+        let uri = model::to_aturi(&event);
+
+        for (feed_index, feed_matcher) in feed_matchers.0.iter().enumerate() {
+            if !feed_matcher.wants_collection(commit.collection())
+                || !feed_matcher.wants_author(&event.did)
+            {
+                continue;
+            }
+
+            if let Some(matcher) = feed_matcher.matched_by(&event_value) {
+                report.feeds[feed_index].matches.push(EventMatch {
+                    line: line_number,
+                    uri: uri.clone(),
+                    matcher,
+                    matcher_kind: config_feeds.feeds[feed_index].matchers[matcher].kind(),
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn harness() {
+        let config_yaml = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/dnd"
+  name: "DnD"
+  description: "DnD"
+  matchers:
+  - path: "$.did"
+    value: "did:plc:cbkjy5n7bk3ax2wplmtjofq2"
+    type: equal
+  - path: "$.commit.record.text"
+    values: ["dnd", "question"]
+    type: sequence
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/likes"
+  name: "Likes"
+  description: "Likes"
+  collections: ["app.bsky.feed.like"]
+  matchers:
+  - path: "$.commit.record.subject.uri"
+    value: "at://did:plc:tgudj2fjm77pzkuawquqhsxm/"
+    type: prefix
+"#;
+
+        let events = r#"{"did":"did:plc:tgudj2fjm77pzkuawquqhsxm","time_us":1730491093829414,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"create","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2u","record":{"$type":"app.bsky.feed.post","createdAt":"2024-11-01T19:58:12.980Z","text":"hey dnd question"},"cid":"bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"}}
+{"did":"did:plc:cbkjy5n7bk3ax2wplmtjofq2","time_us":1730491093829415,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"create","collection":"app.bsky.feed.like","rkey":"3l7vxhiu4kq2v","record":{"$type":"app.bsky.feed.like","createdAt":"2024-11-01T19:58:12.980Z","subject":{"uri":"at://did:plc:tgudj2fjm77pzkuawquqhsxm/app.bsky.feed.post/3l7vxhiu4kq2u","cid":"bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"}},"cid":"bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74"}}
+not json
+
+{"did":"did:plc:cbkjy5n7bk3ax2wplmtjofq2","time_us":1730491093829416,"kind":"commit","commit":{"rev":"3l7vxhiuibq2u","operation":"create","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2w","record":{"$type":"app.bsky.feed.post","createdAt":"2024-11-01T19:58:12.980Z","text":"hello"},"cid":"bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"}}
+{"did":"did:plc:cbkjy5n7bk3ax2wplmtjofq2","time_us":1730491093829417,"kind":"commit","commit":{"rev":"3l7vxhiuibq2x","operation":"delete","collection":"app.bsky.feed.post","rkey":"3l7vxhiu4kq2w"}}
+"#;
+
+        let config_feeds: config::Feeds =
+            serde_yaml::from_str(config_yaml).expect("config is valid");
+        let report = test_matchers(&config_feeds, events.as_bytes()).expect("harness runs");
+
+        assert_eq!(report.events, 4);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, 3);
+
+        let dnd = &report.feeds[0];
+        assert_eq!(
+            dnd.matches
+                .iter()
+                .map(|event_match| (
+                    event_match.line,
+                    event_match.matcher,
+                    event_match.matcher_kind
+                ))
+                .collect::<Vec<_>>(),
+            vec![(1, 1, "sequence"), (5, 0, "equal")]
+        );
+
+        let likes = &report.feeds[1];
+        assert_eq!(likes.matches.len(), 1);
+        assert_eq!(likes.matches[0].line, 2);
+        assert_eq!(
+            likes.matches[0].uri.as_deref(),
+            Some("at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.like/3l7vxhiu4kq2v")
+        );
+    }
+}
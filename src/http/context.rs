@@ -1,22 +1,87 @@
 use axum::extract::FromRef;
+use serde::Serialize;
 use std::{
     collections::{HashMap, HashSet},
     ops::Deref,
     sync::Arc,
+    time::Duration,
 };
+use tokio::sync::RwLock;
 
+use crate::config;
+use crate::metrics::Metrics;
 use crate::storage::StoragePool;
+use crate::vmc::DidResolver;
 
 #[derive(Clone, Debug)]
 pub(crate) struct FeedControl {
     pub(crate) deny: Option<String>,
     pub(crate) allowed: HashSet<String>,
+    pub(crate) hide_seen: bool,
+}
+
+/// A feed as listed by `app.bsky.feed.describeFeedGenerator`.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct FeedDescription {
+    pub(crate) uri: String,
+    pub(crate) name: String,
+    pub(crate) description: String,
+}
+
+/// The configured feeds as seen by request handlers. Reloads replace the whole
+/// snapshot so that a request never sees a mix of old and new feeds.
+#[derive(Debug, Default)]
+pub(crate) struct FeedsSnapshot {
+    pub(crate) controls: HashMap<String, FeedControl>,
+    pub(crate) descriptions: Vec<FeedDescription>,
+}
+
+impl From<&config::Feeds> for FeedsSnapshot {
+    fn from(feeds: &config::Feeds) -> Self {
+        Self {
+            controls: feed_controls(feeds),
+            descriptions: feed_descriptions(feeds),
+        }
+    }
+}
+
+/// Links included in the `app.bsky.feed.describeFeedGenerator` response.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedGeneratorLinks {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privacy_policy: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terms_of_service: Option<String>,
+}
+
+/// What `/readyz` checks beyond database connectivity.
+#[derive(Clone)]
+pub struct ReadinessConfig {
+    /// Whether this process runs the consumer, so its connection can be checked.
+    pub consumer_enabled: bool,
+
+    /// The `consumer_control` source holding the consumer cursor.
+    pub cursor_source: String,
+
+    /// The oldest the stored cursor can be before the service is degraded. Zero
+    /// disables the check. Only checked when this process runs the consumer.
+    pub cursor_max_age: Duration,
+
+    /// Whether this process runs the VMC task, so verification methods missing
+    /// for allowed DIDs can be reported.
+    pub vmc_enabled: bool,
 }
 
 pub struct InnerWebContext {
     pub(crate) pool: StoragePool,
     pub(crate) external_base: String,
-    pub(crate) feeds: HashMap<String, FeedControl>,
+    feeds: RwLock<Arc<FeedsSnapshot>>,
+    pub(crate) links: FeedGeneratorLinks,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) readiness: ReadinessConfig,
+    pub(crate) did_resolver: DidResolver,
 }
 
 #[derive(Clone, FromRef)]
@@ -34,16 +99,57 @@ impl WebContext {
     pub fn new(
         pool: StoragePool,
         external_base: &str,
-        feeds: HashMap<String, (Option<String>, HashSet<String>)>,
+        feeds: &config::Feeds,
+        links: FeedGeneratorLinks,
+        metrics: Arc<Metrics>,
+        readiness: ReadinessConfig,
+        did_resolver: DidResolver,
     ) -> Self {
-        let feeds = feeds
-            .into_iter()
-            .map(|(uri, (deny, allowed))| (uri, FeedControl { deny, allowed }))
-            .collect();
         Self(Arc::new(InnerWebContext {
             pool,
             external_base: external_base.to_string(),
-            feeds,
+            feeds: RwLock::new(Arc::new(feeds.into())),
+            links,
+            metrics,
+            readiness,
+            did_resolver,
         }))
     }
+
+    pub(crate) async fn feeds(&self) -> Arc<FeedsSnapshot> {
+        self.feeds.read().await.clone()
+    }
+
+    pub async fn update_feeds(&self, feeds: &config::Feeds) {
+        *self.feeds.write().await = Arc::new(feeds.into());
+    }
+}
+
+fn feed_controls(feeds: &config::Feeds) -> HashMap<String, FeedControl> {
+    feeds
+        .feeds
+        .iter()
+        .map(|feed| {
+            (
+                feed.uri.clone(),
+                FeedControl {
+                    deny: feed.deny.clone(),
+                    allowed: feed.allow.clone(),
+                    hide_seen: feed.hide_seen,
+                },
+            )
+        })
+        .collect()
+}
+
+fn feed_descriptions(feeds: &config::Feeds) -> Vec<FeedDescription> {
+    feeds
+        .feeds
+        .iter()
+        .map(|feed| FeedDescription {
+            uri: feed.uri.clone(),
+            name: feed.name.clone(),
+            description: feed.description.clone(),
+        })
+        .collect()
 }
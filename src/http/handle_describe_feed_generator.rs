@@ -1,15 +1,71 @@
 use anyhow::Result;
 use axum::{extract::State, response::IntoResponse, Json};
-use serde_json::json;
+use serde_json::{json, Value};
 
 use crate::errors::SupercellError;
 
-use super::context::WebContext;
+use super::context::{FeedDescription, FeedGeneratorLinks, WebContext};
 
 pub async fn handle_describe_feed_generator(
     State(web_context): State<WebContext>,
 ) -> Result<impl IntoResponse, SupercellError> {
-    Ok(Json(json!({
-        "feeds": web_context.feeds.keys().map(|k| json!({"uri": k})).collect::<Vec<_>>(),
-    })))
+    Ok(Json(describe_feed_generator(
+        &web_context.external_base,
+        &web_context.feeds().await.descriptions,
+        &web_context.links,
+    )))
+}
+
+fn describe_feed_generator(
+    external_base: &str,
+    feeds: &[FeedDescription],
+    links: &FeedGeneratorLinks,
+) -> Value {
+    let mut response = json!({
+        "did": format!("did:web:{}", external_base),
+        "feeds": feeds,
+    });
+    if links.privacy_policy.is_some() || links.terms_of_service.is_some() {
+        response["links"] = json!(links);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe() {
+        let feeds = vec![FeedDescription {
+            uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/3la5azib4xe2c"
+                .to_string(),
+            name: "Smoke Signal Support".to_string(),
+            description: "The Smoke Signal Support feed.".to_string(),
+        }];
+
+        let response =
+            describe_feed_generator("feeds.example.com", &feeds, &FeedGeneratorLinks::default());
+        assert_eq!(
+            response,
+            json!({
+                "did": "did:web:feeds.example.com",
+                "feeds": [{
+                    "uri": "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/3la5azib4xe2c",
+                    "name": "Smoke Signal Support",
+                    "description": "The Smoke Signal Support feed.",
+                }],
+            })
+        );
+
+        let links = FeedGeneratorLinks {
+            privacy_policy: Some("https://example.com/privacy".to_string()),
+            terms_of_service: None,
+        };
+        let response = describe_feed_generator("feeds.example.com", &feeds, &links);
+        assert_eq!(
+            response["links"],
+            json!({"privacyPolicy": "https://example.com/privacy"})
+        );
+    }
 }
@@ -1,19 +1,25 @@
 use anyhow::{anyhow, Context, Result};
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
 use axum_extra::extract::Query;
 use base64::{engine::general_purpose, Engine as _};
 use chrono::Utc;
 use http::{HeaderMap, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::time::Instant;
 
 use crate::errors::SupercellError;
-use crate::storage::feed_content_paginate;
-use crate::storage::{verification_method_get, StoragePool};
+use crate::metrics::FeedLabels;
+use crate::storage::{feed_content_paginate, feed_content_paginate_unseen};
+use crate::vmc::DidResolver;
 
 use crate::crypto::{validate, JwtClaims, JwtHeader};
 
-use super::context::WebContext;
+use super::context::{FeedControl, WebContext};
 
 #[derive(Deserialize, Default)]
 pub struct FeedParams {
@@ -39,12 +45,38 @@ pub async fn handle_get_feed_skeleton(
     Query(feed_params): Query<FeedParams>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, SupercellError> {
+    let start = Instant::now();
+    let feed_uri = feed_params.feed.clone();
+
+    let response = get_feed_skeleton(&web_context, feed_params, headers).await;
+
+    // Only configured feeds are labelled so that arbitrary feed parameters
+    // cannot create new series.
+    if let Some(feed_uri) = feed_uri {
+        if web_context.feeds().await.controls.contains_key(&feed_uri) {
+            web_context
+                .metrics
+                .feed_skeleton
+                .duration_seconds
+                .get_or_create(&FeedLabels { feed: feed_uri })
+                .observe(start.elapsed().as_secs_f64());
+        }
+    }
+
+    response
+}
+
+async fn get_feed_skeleton(
+    web_context: &WebContext,
+    feed_params: FeedParams,
+    headers: HeaderMap,
+) -> Result<Response, SupercellError> {
     if feed_params.feed.is_none() {
         return Err(anyhow!("feed parameter is required").into());
     }
     let feed_uri = feed_params.feed.unwrap();
 
-    let feed_control = web_context.feeds.get(&feed_uri);
+    let feed_control = web_context.feeds().await.controls.get(&feed_uri).cloned();
     if feed_control.is_none() {
         return Ok((
             StatusCode::BAD_REQUEST,
@@ -58,60 +90,100 @@ pub async fn handle_get_feed_skeleton(
 
     let feed_control = feed_control.unwrap();
 
+    let authorization = headers.get("Authorization").and_then(|value| {
+        value
+            .to_str()
+            .map(|inner_value| inner_value.to_string())
+            .ok()
+    });
+
+    // Resolving the issuer spends the shared resolution budget, so tokens that
+    // can never be admitted to an allow-listed feed are denied before that.
     if !feed_control.allowed.is_empty() {
-        let authorization = headers.get("Authorization").and_then(|value| {
-            value
-                .to_str()
-                .map(|inner_value| inner_value.to_string())
-                .ok()
-        });
-
-        let did = did_from_jwt(&web_context.pool, &web_context.external_base, authorization).await;
-
-        if let Err(err) = did {
-            tracing::error!(error = ?err, "failed to validate JWT");
-            return Ok(Json(FeedItemsView {
-                cursor: None,
-                feed: feed_control
-                    .deny
-                    .as_ref()
-                    .map(|value| {
-                        vec![FeedItemView {
-                            post: value.clone(),
-                        }]
-                    })
-                    .unwrap_or(vec![]),
-            })
-            .into_response());
+        match authorization.as_deref().and_then(unverified_issuer) {
+            Some(issuer) if feed_control.allowed.contains(&issuer) => {}
+            issuer => {
+                web_context.metrics.feed_skeleton.rejection(
+                    &feed_uri,
+                    if issuer.is_some() {
+                        "not_allowed"
+                    } else {
+                        "invalid"
+                    },
+                );
+                return Ok(deny_response(&feed_control));
+            }
+        }
+    }
+
+    // The viewer is identified whenever a token is presented. Feeds with an
+    // allow list require a valid token from an allowed DID, while other feeds
+    // are served anonymously when there is no token or it is invalid.
+    let viewer = match authorization {
+        Some(authorization) => {
+            did_from_jwt(
+                &web_context.did_resolver,
+                &web_context.external_base,
+                "app.bsky.feed.getFeedSkeleton",
+                Some(authorization),
+            )
+            .await
         }
+        None => Err(anyhow!("no authorization token")),
+    };
 
-        let did = did.unwrap();
+    let viewer = if feed_control.allowed.is_empty() {
+        viewer
+            .inspect_err(|err| tracing::debug!(error = ?err, "serving feed without a viewer"))
+            .ok()
+    } else {
+        let did = match viewer {
+            Ok(did) => did,
+            Err(err) => {
+                tracing::error!(error = ?err, "failed to validate JWT");
+                web_context
+                    .metrics
+                    .feed_skeleton
+                    .rejection(&feed_uri, "invalid");
+                return Ok(deny_response(&feed_control));
+            }
+        };
 
         if !feed_control.allowed.contains(&did) {
-            return Ok(Json(FeedItemsView {
-                cursor: None,
-                feed: feed_control
-                    .deny
-                    .as_ref()
-                    .map(|value| {
-                        vec![FeedItemView {
-                            post: value.clone(),
-                        }]
-                    })
-                    .unwrap_or(vec![]),
-            })
-            .into_response());
+            web_context
+                .metrics
+                .feed_skeleton
+                .rejection(&feed_uri, "not_allowed");
+            return Ok(deny_response(&feed_control));
         }
-    }
+
+        Some(did)
+    };
+
+    tracing::debug!(feed_id = ?feed_uri, viewer = ?viewer, "feed skeleton requested");
 
     let parsed_cursor = parse_cursor(feed_params.cursor);
-    let feed_items = feed_content_paginate(
-        &web_context.pool,
-        &feed_uri,
-        feed_params.limit,
-        parsed_cursor,
-    )
-    .await?;
+    let feed_items = match viewer.as_deref().filter(|_| feed_control.hide_seen) {
+        Some(viewer) => {
+            feed_content_paginate_unseen(
+                &web_context.pool,
+                &feed_uri,
+                viewer,
+                feed_params.limit,
+                parsed_cursor,
+            )
+            .await?
+        }
+        None => {
+            feed_content_paginate(
+                &web_context.pool,
+                &feed_uri,
+                feed_params.limit,
+                parsed_cursor,
+            )
+            .await?
+        }
+    };
 
     let cursor = feed_items
         .iter()
@@ -132,6 +204,22 @@ pub async fn handle_get_feed_skeleton(
     .into_response())
 }
 
+fn deny_response(feed_control: &FeedControl) -> Response {
+    Json(FeedItemsView {
+        cursor: None,
+        feed: feed_control
+            .deny
+            .as_ref()
+            .map(|value| {
+                vec![FeedItemView {
+                    post: value.clone(),
+                }]
+            })
+            .unwrap_or(vec![]),
+    })
+    .into_response()
+}
+
 pub fn split_token(token: &str) -> Result<[&str; 3]> {
     let mut components = token.split('.');
     let header = components.next().ok_or(anyhow!("missing header"))?;
@@ -145,9 +233,20 @@ pub fn split_token(token: &str) -> Result<[&str; 3]> {
     Ok([header, claims, signature])
 }
 
-async fn did_from_jwt(
-    pool: &StoragePool,
+/// Verifies a service-auth JWT for the `lxm` method, returning its issuer.
+/// Returns the issuer claimed by a bearer token without verifying the token.
+fn unverified_issuer(authorization: &str) -> Option<String> {
+    let jwt = authorization.strip_prefix("Bearer ")?;
+    let [_, claims_part, _] = split_token(jwt).ok()?;
+    let content = general_purpose::URL_SAFE_NO_PAD.decode(claims_part).ok()?;
+    let claims: JwtClaims = serde_json::from_slice(&content).ok()?;
+    Some(claims.iss)
+}
+
+pub(crate) async fn did_from_jwt(
+    did_resolver: &DidResolver,
     external_base: &str,
+    lxm: &str,
     authorization: Option<String>,
 ) -> Result<String> {
     let jwt = authorization
@@ -175,10 +274,10 @@ async fn did_from_jwt(
     let now = Utc::now();
     let now = now.timestamp() as i32;
 
-    if header.alg != "ES256K" {
+    if header.alg != "ES256K" && header.alg != "ES256" {
         return Err(anyhow!("unsupported algorithm"));
     }
-    if claims.lxm != "app.bsky.feed.getFeedSkeleton" {
+    if claims.lxm != lxm {
         return Err(anyhow!("invalid resource"));
     }
     if claims.aud != format!("did:web:{}", external_base) {
@@ -191,11 +290,7 @@ async fn did_from_jwt(
         return Err(anyhow!("token issued in the future"));
     }
 
-    let multibase = verification_method_get(pool, &claims.iss).await?;
-    if multibase.is_none() {
-        return Err(anyhow!("verification method not found"));
-    }
-    let multibase = multibase.unwrap();
+    let multibase = did_resolver.verification_method(&claims.iss).await?;
 
     let signature = general_purpose::URL_SAFE_NO_PAD
         .decode(signature_part)
@@ -204,7 +299,15 @@ async fn did_from_jwt(
 
     let content = format!("{}.{}", header_part, claims_part);
 
-    validate(&multibase, signature, &content)?;
+    // A failed verification may mean the issuer rotated its key, so the DID
+    // is resolved again once before giving up.
+    if let Err(err) = validate(&multibase, &header.alg, signature, &content) {
+        let refreshed = match did_resolver.refresh(&claims.iss).await {
+            Ok(refreshed) if refreshed != multibase => refreshed,
+            _ => return Err(err),
+        };
+        validate(&refreshed, &header.alg, signature, &content)?;
+    }
 
     Ok(claims.iss)
 }
@@ -225,3 +328,219 @@ fn parse_cursor(value: Option<String>) -> Option<(i64, String)> {
 
     Some((time_us, parts[1].to_string()))
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::crypto::tests::generate_key;
+    use crate::http::context::ReadinessConfig;
+    use crate::storage::{verifcation_method_insert, StoragePool};
+    use crate::vmc::tests::{did_resolver, serve_plc, verification_method, MockPlc};
+
+    const SKELETON: &str = "app.bsky.feed.getFeedSkeleton";
+
+    pub(crate) fn service_auth_jwt(
+        alg: &str,
+        iss: &str,
+        lxm: &str,
+        sign: &dyn Fn(&[u8]) -> Vec<u8>,
+    ) -> String {
+        let now = Utc::now().timestamp();
+        let header =
+            general_purpose::URL_SAFE_NO_PAD.encode(json!({"typ": "JWT", "alg": alg}).to_string());
+        let claims = general_purpose::URL_SAFE_NO_PAD.encode(
+            json!({
+                "iss": iss,
+                "aud": "did:web:feeds.example.com",
+                "iat": now,
+                "exp": now + 60,
+                "lxm": lxm,
+            })
+            .to_string(),
+        );
+        let content = format!("{}.{}", header, claims);
+        let signature = general_purpose::URL_SAFE_NO_PAD.encode(sign(content.as_bytes()));
+        format!("Bearer {}.{}", content, signature)
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn did_from_jwt_both_curves(pool: SqlitePool) -> Result<()> {
+        let pool = StoragePool::from(pool);
+        let plc = MockPlc::default();
+        let did_resolver = did_resolver(pool.clone(), serve_plc(plc.clone()).await, 60);
+
+        // One issuer is already cached, the other is resolved on demand.
+        let (k256_sign, k256_key) = generate_key("ES256K");
+        verifcation_method_insert(&pool, &verification_method("did:plc:k256", &k256_key)).await?;
+        let (p256_sign, p256_key) = generate_key("ES256");
+        plc.keys
+            .lock()
+            .unwrap()
+            .insert("did:plc:p256".to_string(), p256_key);
+
+        for (alg, did, sign) in [
+            ("ES256K", "did:plc:k256", &k256_sign),
+            ("ES256", "did:plc:p256", &p256_sign),
+        ] {
+            let jwt = service_auth_jwt(alg, did, SKELETON, sign);
+            let validated =
+                did_from_jwt(&did_resolver, "feeds.example.com", SKELETON, Some(jwt)).await?;
+            assert_eq!(validated, did);
+        }
+
+        // A token that claims a different algorithm than the issuer's key.
+        let jwt = service_auth_jwt("ES256K", "did:plc:k256", SKELETON, &p256_sign);
+        assert!(
+            did_from_jwt(&did_resolver, "feeds.example.com", SKELETON, Some(jwt))
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn did_from_jwt_rotated_key(pool: SqlitePool) -> Result<()> {
+        let pool = StoragePool::from(pool);
+        let plc = MockPlc::default();
+        let did_resolver = did_resolver(pool.clone(), serve_plc(plc.clone()).await, 60);
+
+        let (_, old_key) = generate_key("ES256K");
+        verifcation_method_insert(&pool, &verification_method("did:plc:rotated", &old_key)).await?;
+        let (sign, new_key) = generate_key("ES256K");
+        plc.keys
+            .lock()
+            .unwrap()
+            .insert("did:plc:rotated".to_string(), new_key);
+
+        let jwt = service_auth_jwt("ES256K", "did:plc:rotated", SKELETON, &sign);
+        let validated =
+            did_from_jwt(&did_resolver, "feeds.example.com", SKELETON, Some(jwt)).await?;
+        assert_eq!(validated, "did:plc:rotated");
+        assert_eq!(*plc.requests.lock().unwrap(), 1);
+
+        Ok(())
+    }
+
+    const FEEDS_YAML: &str = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/public"
+  name: "Public"
+  description: "A public feed."
+  matchers: []
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/private"
+  name: "Private"
+  description: "A private feed."
+  allow: ["did:plc:viewer"]
+  deny: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.post/denied"
+  matchers: []
+"#;
+
+    pub(crate) fn web_context(pool: StoragePool, plc_url: String, feeds_yaml: &str) -> WebContext {
+        let feeds: crate::config::Feeds = serde_yaml::from_str(feeds_yaml).unwrap();
+        WebContext::new(
+            pool.clone(),
+            "feeds.example.com",
+            &feeds,
+            Default::default(),
+            Default::default(),
+            ReadinessConfig {
+                consumer_enabled: false,
+                cursor_source: String::new(),
+                cursor_max_age: std::time::Duration::ZERO,
+                vmc_enabled: false,
+            },
+            did_resolver(pool, plc_url, 60),
+        )
+    }
+
+    pub(crate) async fn response_json(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    pub(crate) async fn request_feed(
+        web_context: &WebContext,
+        feed: &str,
+        authorization: Option<String>,
+    ) -> Result<serde_json::Value> {
+        let mut headers = HeaderMap::new();
+        if let Some(authorization) = authorization {
+            headers.insert("Authorization", authorization.parse()?);
+        }
+        let feed_params = FeedParams {
+            feed: Some(format!(
+                "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/{}",
+                feed
+            )),
+            ..Default::default()
+        };
+        let response = get_feed_skeleton(web_context, feed_params, headers)
+            .await
+            .map_err(|err| err.0)?;
+        Ok(response_json(response).await)
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn viewer_optional(pool: SqlitePool) -> Result<()> {
+        let pool = StoragePool::from(pool);
+        let plc = MockPlc::default();
+        let web_context = web_context(pool.clone(), serve_plc(plc.clone()).await, FEEDS_YAML);
+
+        let (sign, key) = generate_key("ES256K");
+        verifcation_method_insert(&pool, &verification_method("did:plc:viewer", &key)).await?;
+        let valid = service_auth_jwt("ES256K", "did:plc:viewer", SKELETON, &sign);
+        let (other_sign, _) = generate_key("ES256K");
+        let invalid = service_auth_jwt("ES256K", "did:plc:viewer", SKELETON, &other_sign);
+
+        crate::storage::feed_content_insert(
+            &pool,
+            &crate::storage::model::FeedContent {
+                feed_id: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/public"
+                    .to_string(),
+                uri: "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.post/3la5b2tn4ix2c"
+                    .to_string(),
+                indexed_at: 1,
+                cid: "bafyreia".to_string(),
+            },
+        )
+        .await?;
+
+        // Public feeds are served with or without a viewer, even when the
+        // token does not verify.
+        for authorization in [None, Some(valid.clone()), Some(invalid.clone())] {
+            let response = request_feed(&web_context, "public", authorization).await?;
+            assert_eq!(
+                response["feed"][0]["post"],
+                "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.post/3la5b2tn4ix2c"
+            );
+        }
+
+        // Private feeds still require a valid token from an allowed viewer.
+        let denied = json!({"feed": [{"post": "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.post/denied"}]});
+        assert_eq!(request_feed(&web_context, "private", None).await?, denied);
+        assert_eq!(
+            request_feed(&web_context, "private", Some(invalid)).await?,
+            denied
+        );
+        assert_eq!(
+            request_feed(&web_context, "private", Some(valid)).await?,
+            json!({"feed": []})
+        );
+
+        // Issuers that are not allowed are denied without being resolved.
+        let requests = *plc.requests.lock().unwrap();
+        let stranger = service_auth_jwt("ES256K", "did:plc:stranger", SKELETON, &other_sign);
+        assert_eq!(
+            request_feed(&web_context, "private", Some(stranger)).await?,
+            denied
+        );
+        assert_eq!(*plc.requests.lock().unwrap(), requests);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::storage::{consumer_control_get, verification_method_get};
+
+use super::context::WebContext;
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            message: None,
+        }
+    }
+
+    fn failed(message: String) -> Self {
+        Self {
+            ok: false,
+            message: Some(message),
+        }
+    }
+
+    /// A problem worth reporting that does not stop the service from serving.
+    fn warning(message: String) -> Self {
+        Self {
+            ok: true,
+            message: Some(message),
+        }
+    }
+}
+
+/// Liveness: the process is up and serving requests.
+pub async fn handle_healthz() -> impl IntoResponse {
+    Json(json!({"ok": true}))
+}
+
+/// Readiness: the service can serve feeds. Responds with a breakdown of each
+/// check and a 503 if any of them failed.
+pub async fn handle_readyz(State(web_context): State<WebContext>) -> impl IntoResponse {
+    let mut checks = BTreeMap::new();
+
+    let cursor =
+        consumer_control_get(&web_context.pool, &web_context.readiness.cursor_source).await;
+    checks.insert(
+        "database",
+        match &cursor {
+            Ok(_) => Check::ok(),
+            Err(err) => Check::failed(format!("{:#}", err)),
+        },
+    );
+
+    // Only the process running the consumer connects to jetstream and stores
+    // the cursor.
+    if web_context.readiness.consumer_enabled {
+        checks.insert(
+            "consumer",
+            if web_context.metrics.consumer.connected.get() > 0 {
+                Check::ok()
+            } else {
+                Check::failed("not connected to jetstream".to_string())
+            },
+        );
+
+        let cursor_max_age = web_context.readiness.cursor_max_age;
+        if !cursor_max_age.is_zero() {
+            if let Ok(cursor) = cursor {
+                checks.insert("cursor", cursor_check(cursor, cursor_max_age));
+            }
+        }
+    }
+
+    if web_context.readiness.vmc_enabled {
+        checks.insert(
+            "verification_methods",
+            verification_methods_check(&web_context).await,
+        );
+    }
+
+    let ready = checks.values().all(|check| check.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(json!({"ready": ready, "checks": checks})))
+}
+
+fn cursor_check(cursor: Option<i64>, max_age: std::time::Duration) -> Check {
+    let Some(cursor) = cursor else {
+        return Check::failed("no cursor has been stored".to_string());
+    };
+
+    let age = chrono::Utc::now().timestamp_micros().saturating_sub(cursor);
+    let max_age = i64::try_from(max_age.as_micros()).unwrap_or(i64::MAX);
+    if age > max_age {
+        Check::failed(format!("cursor is {}s old", age / 1_000_000))
+    } else {
+        Check::ok()
+    }
+}
+
+/// Reports allowed DIDs without a cached verification method. Missing keys are
+/// resolved on demand, so they do not fail readiness.
+async fn verification_methods_check(web_context: &WebContext) -> Check {
+    let dids: BTreeSet<String> = web_context
+        .feeds()
+        .await
+        .controls
+        .values()
+        .flat_map(|feed_control| feed_control.allowed.iter().cloned())
+        .collect();
+
+    let mut missing = Vec::new();
+    for did in dids {
+        match verification_method_get(&web_context.pool, &did).await {
+            Ok(Some(_)) => {}
+            Ok(None) => missing.push(did),
+            Err(err) => return Check::failed(format!("{:#}", err)),
+        }
+    }
+
+    if missing.is_empty() {
+        Check::ok()
+    } else {
+        Check::warning(format!("no verification method for {}", missing.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::response::Response;
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::http::context::ReadinessConfig;
+    use crate::http::handle_get_feed_skeleton::tests::response_json;
+    use crate::storage::StoragePool;
+    use crate::vmc::tests::did_resolver;
+
+    const FEEDS_YAML: &str = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/private"
+  name: "Private"
+  description: "A private feed."
+  allow: ["did:plc:cbkjy5n7bk3ax2wplmtjofq2"]
+  matchers: []
+"#;
+
+    async fn readyz(
+        pool: StoragePool,
+        readiness: ReadinessConfig,
+    ) -> (StatusCode, serde_json::Value) {
+        let feeds: crate::config::Feeds = serde_yaml::from_str(FEEDS_YAML).unwrap();
+        let web_context = WebContext::new(
+            pool.clone(),
+            "feeds.example.com",
+            &feeds,
+            Default::default(),
+            Default::default(),
+            readiness,
+            did_resolver(pool, "http://localhost".to_string(), 60),
+        );
+        let response: Response = handle_readyz(State(web_context)).await.into_response();
+        (response.status(), response_json(response).await)
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn readiness(pool: SqlitePool) -> anyhow::Result<()> {
+        let pool = StoragePool::from(pool);
+
+        // An API only replica has no cursor to check, and a missing verification
+        // method is reported without failing readiness.
+        let (status, body) = readyz(
+            pool.clone(),
+            ReadinessConfig {
+                consumer_enabled: false,
+                cursor_source: "localhost".to_string(),
+                cursor_max_age: Duration::from_secs(600),
+                vmc_enabled: true,
+            },
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({
+                "ready": true,
+                "checks": {
+                    "database": {"ok": true},
+                    "verification_methods": {
+                        "ok": true,
+                        "message": "no verification method for did:plc:cbkjy5n7bk3ax2wplmtjofq2",
+                    },
+                },
+            })
+        );
+
+        // The consumer is checked where it runs.
+        let (status, body) = readyz(
+            pool,
+            ReadinessConfig {
+                consumer_enabled: true,
+                cursor_source: "localhost".to_string(),
+                cursor_max_age: Duration::from_secs(600),
+                vmc_enabled: false,
+            },
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body["checks"],
+            json!({
+                "consumer": {"ok": false, "message": "not connected to jetstream"},
+                "cursor": {"ok": false, "message": "no cursor has been stored"},
+                "database": {"ok": true},
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cursor_freshness() {
+        let max_age = Duration::from_secs(600);
+        let now = chrono::Utc::now().timestamp_micros();
+
+        assert!(cursor_check(Some(now - 60_000_000), max_age).ok);
+        assert!(!cursor_check(Some(now - 3_600_000_000), max_age).ok);
+        assert!(!cursor_check(None, max_age).ok);
+    }
+}
@@ -0,0 +1,19 @@
+use axum::{extract::State, response::IntoResponse};
+use http::header::CONTENT_TYPE;
+
+use crate::errors::SupercellError;
+
+use super::context::WebContext;
+
+pub async fn handle_metrics(
+    State(web_context): State<WebContext>,
+) -> Result<impl IntoResponse, SupercellError> {
+    let body = web_context.metrics.encode()?;
+    Ok((
+        [(
+            CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        body,
+    ))
+}
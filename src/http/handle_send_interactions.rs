@@ -0,0 +1,274 @@
+use anyhow::Result;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::{HeaderMap, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::errors::SupercellError;
+use crate::storage::{feed_interaction_insert, model::InteractionEvent};
+
+use super::context::WebContext;
+use super::handle_get_feed_skeleton::did_from_jwt;
+
+const MAX_INTERACTIONS: usize = 1000;
+
+/// The most interactions kept for each viewer. Older ones are deleted as new
+/// ones are recorded.
+const MAX_VIEWER_INTERACTIONS: i64 = 10_000;
+
+#[derive(Deserialize)]
+pub struct Interaction {
+    pub item: Option<String>,
+    pub event: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SendInteractionsInput {
+    pub interactions: Vec<Interaction>,
+}
+
+/// Records the `seen`, `requestLess`, and `requestMore` interactions of the
+/// verified viewer with posts in a feed. Other interaction events, and items
+/// that are not post URIs, are accepted and ignored.
+pub async fn handle_send_interactions(
+    State(web_context): State<WebContext>,
+    headers: HeaderMap,
+    Json(input): Json<SendInteractionsInput>,
+) -> Result<Response, SupercellError> {
+    let authorization = headers.get("Authorization").and_then(|value| {
+        value
+            .to_str()
+            .map(|inner_value| inner_value.to_string())
+            .ok()
+    });
+
+    let viewer = match did_from_jwt(
+        &web_context.did_resolver,
+        &web_context.external_base,
+        "app.bsky.feed.sendInteractions",
+        authorization,
+    )
+    .await
+    {
+        Ok(viewer) => viewer,
+        Err(err) => {
+            tracing::debug!(error = ?err, "failed to validate JWT");
+            return Ok((
+                StatusCode::UNAUTHORIZED,
+                Json(json!({
+                    "error": "AuthenticationRequired",
+                    "message": "a valid service auth token is required",
+                })),
+            )
+                .into_response());
+        }
+    };
+
+    if input.interactions.len() > MAX_INTERACTIONS {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": "InvalidRequest",
+                "message": format!("at most {} interactions can be sent at once", MAX_INTERACTIONS),
+            })),
+        )
+            .into_response());
+    }
+
+    let interactions = input
+        .interactions
+        .into_iter()
+        .filter_map(|interaction| {
+            let event = match interaction.event?.as_str() {
+                "app.bsky.feed.defs#interactionSeen" => InteractionEvent::Seen,
+                "app.bsky.feed.defs#requestLess" => InteractionEvent::RequestLess,
+                "app.bsky.feed.defs#requestMore" => InteractionEvent::RequestMore,
+                _ => return None,
+            };
+            let item = interaction.item.filter(|item| is_post_uri(item))?;
+            Some((item, event))
+        })
+        .collect::<Vec<_>>();
+
+    if !interactions.is_empty() {
+        feed_interaction_insert(
+            &web_context.pool,
+            &viewer,
+            &interactions,
+            MAX_VIEWER_INTERACTIONS,
+        )
+        .await?;
+    }
+
+    Ok(Json(json!({})).into_response())
+}
+
+/// Returns true for `at://<did>/app.bsky.feed.post/<rkey>` URIs.
+fn is_post_uri(item: &str) -> bool {
+    let Some(path) = item.strip_prefix("at://") else {
+        return false;
+    };
+    let parts = path.split('/').collect::<Vec<_>>();
+    let [did, collection, rkey] = parts.as_slice() else {
+        return false;
+    };
+
+    did.starts_with("did:")
+        && *collection == "app.bsky.feed.post"
+        && !rkey.is_empty()
+        && rkey.len() <= 512
+        && rkey
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".-_:~".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::crypto::tests::generate_key;
+    use crate::http::handle_get_feed_skeleton::tests::{
+        request_feed, response_json, service_auth_jwt, web_context,
+    };
+    use crate::storage::{
+        feed_content_insert, model::FeedContent, verifcation_method_insert, StoragePool,
+    };
+    use crate::vmc::tests::{serve_plc, verification_method, MockPlc};
+
+    const FEEDS_YAML: &str = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/public"
+  name: "Public"
+  description: "A public feed that hides seen posts."
+  hide_seen: true
+  matchers: []
+"#;
+
+    fn post(rkey: &str) -> String {
+        format!(
+            "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.post/{}",
+            rkey
+        )
+    }
+
+    async fn send_interactions(
+        web_context: &WebContext,
+        authorization: Option<String>,
+        interactions: serde_json::Value,
+    ) -> Result<(StatusCode, serde_json::Value)> {
+        let mut headers = HeaderMap::new();
+        if let Some(authorization) = authorization {
+            headers.insert("Authorization", authorization.parse()?);
+        }
+        let input = serde_json::from_value(json!({ "interactions": interactions }))?;
+        let response = handle_send_interactions(State(web_context.clone()), headers, Json(input))
+            .await
+            .map_err(|err| err.0)?;
+        Ok((response.status(), response_json(response).await))
+    }
+
+    #[test]
+    fn post_uris() {
+        assert!(is_post_uri(&post("3la5b2tn4ix2c")));
+        assert!(is_post_uri(
+            "at://did:web:feeds.example.com/app.bsky.feed.post/a.b-c_d:e~f"
+        ));
+
+        for item in [
+            "",
+            "not a uri",
+            "https://bsky.app/profile/did:plc:cbkjy5n7bk3ax2wplmtjofq2/post/3la5b2tn4ix2c",
+            "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.like/3la5b2tn4ix2c",
+            "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.post/",
+            "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.post/a/b",
+            "at://smokesignal.events/app.bsky.feed.post/3la5b2tn4ix2c",
+            "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.post/3la5 b2tn4ix2c",
+        ] {
+            assert!(!is_post_uri(item), "{}", item);
+        }
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn hide_seen_posts(pool: SqlitePool) -> Result<()> {
+        let pool = StoragePool::from(pool);
+        let web_context = web_context(
+            pool.clone(),
+            serve_plc(MockPlc::default()).await,
+            FEEDS_YAML,
+        );
+
+        let (sign, key) = generate_key("ES256");
+        verifcation_method_insert(&pool, &verification_method("did:plc:viewer", &key)).await?;
+        let interactions_jwt = service_auth_jwt(
+            "ES256",
+            "did:plc:viewer",
+            "app.bsky.feed.sendInteractions",
+            &sign,
+        );
+        let skeleton_jwt = service_auth_jwt(
+            "ES256",
+            "did:plc:viewer",
+            "app.bsky.feed.getFeedSkeleton",
+            &sign,
+        );
+
+        for (indexed_at, rkey) in [(1, "first"), (2, "second"), (3, "third")] {
+            feed_content_insert(
+                &pool,
+                &FeedContent {
+                    feed_id: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/public"
+                        .to_string(),
+                    uri: post(rkey),
+                    indexed_at,
+                    cid: "bafyreia".to_string(),
+                },
+            )
+            .await?;
+        }
+
+        let interactions = json!([
+            {"item": post("third"), "event": "app.bsky.feed.defs#interactionSeen"},
+            {"item": post("second"), "event": "app.bsky.feed.defs#requestLess"},
+            {"item": post("first"), "event": "app.bsky.feed.defs#interactionLike"},
+            {"item": post("unknown"), "event": "app.bsky.feed.defs#interactionSeen"},
+            {"item": "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.like/first", "event": "app.bsky.feed.defs#interactionSeen"},
+            {"item": "not a uri", "event": "app.bsky.feed.defs#interactionSeen"},
+        ]);
+
+        // Interactions require a token for sendInteractions.
+        for authorization in [None, Some(skeleton_jwt.clone())] {
+            let (status, _) =
+                send_interactions(&web_context, authorization, interactions.clone()).await?;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        let (status, body) =
+            send_interactions(&web_context, Some(interactions_jwt), interactions).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({}));
+
+        let response = request_feed(&web_context, "public", Some(skeleton_jwt)).await?;
+        assert_eq!(response["feed"], json!([{"post": post("first")}]));
+
+        // Anonymous viewers get every post.
+        let response = request_feed(&web_context, "public", None).await?;
+        assert_eq!(response["feed"].as_array().map(Vec::len), Some(3));
+
+        // Only the interactions with posts in the feed were stored.
+        assert_eq!(
+            crate::storage::feed_interaction_expire(
+                &pool,
+                chrono::Utc::now() + chrono::Duration::hours(1)
+            )
+            .await?,
+            2
+        );
+
+        Ok(())
+    }
+}
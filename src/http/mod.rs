@@ -1,6 +1,9 @@
 pub mod context;
 pub mod handle_describe_feed_generator;
 pub mod handle_get_feed_skeleton;
+pub mod handle_health;
 pub mod handle_index;
+pub mod handle_metrics;
+pub mod handle_send_interactions;
 pub mod handle_well_known;
 pub mod server;
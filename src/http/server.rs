@@ -1,9 +1,18 @@
 use super::{
-    context::WebContext, handle_describe_feed_generator::handle_describe_feed_generator,
-    handle_get_feed_skeleton::handle_get_feed_skeleton, handle_index::handle_index,
+    context::WebContext,
+    handle_describe_feed_generator::handle_describe_feed_generator,
+    handle_get_feed_skeleton::handle_get_feed_skeleton,
+    handle_health::{handle_healthz, handle_readyz},
+    handle_index::handle_index,
+    handle_metrics::handle_metrics,
+    handle_send_interactions::handle_send_interactions,
     handle_well_known::handle_well_known,
 };
-use axum::{http::HeaderValue, routing::get, Router};
+use axum::{
+    http::HeaderValue,
+    routing::{get, post},
+    Router,
+};
 use http::{
     header::{ACCEPT, ACCEPT_LANGUAGE},
     Method,
@@ -13,9 +22,13 @@ use tower_http::cors::CorsLayer;
 use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
 
-pub fn build_router(web_context: WebContext) -> Router {
-    Router::new()
+/// Builds the public router. `/metrics` is only included when `serve_metrics`
+/// is set, otherwise it is expected to be served by `build_admin_router`.
+pub fn build_router(web_context: WebContext, serve_metrics: bool) -> Router {
+    let router = Router::new()
         .route("/", get(handle_index))
+        .route("/healthz", get(handle_healthz))
+        .route("/readyz", get(handle_readyz))
         .route("/.well-known/did.json", get(handle_well_known))
         .route(
             "/xrpc/app.bsky.feed.getFeedSkeleton",
@@ -25,6 +38,18 @@ pub fn build_router(web_context: WebContext) -> Router {
             "/xrpc/app.bsky.feed.describeFeedGenerator",
             get(handle_describe_feed_generator),
         )
+        .route(
+            "/xrpc/app.bsky.feed.sendInteractions",
+            post(handle_send_interactions),
+        );
+
+    let router = if serve_metrics {
+        router.route("/metrics", get(handle_metrics))
+    } else {
+        router
+    };
+
+    router
         .layer((
             TraceLayer::new_for_http(),
             TimeoutLayer::new(Duration::from_secs(10)),
@@ -37,3 +62,16 @@ pub fn build_router(web_context: WebContext) -> Router {
         )
         .with_state(web_context.clone())
 }
+
+/// Builds the router served on the admin port.
+pub fn build_admin_router(web_context: WebContext) -> Router {
+    Router::new()
+        .route("/healthz", get(handle_healthz))
+        .route("/readyz", get(handle_readyz))
+        .route("/metrics", get(handle_metrics))
+        .layer((
+            TraceLayer::new_for_http(),
+            TimeoutLayer::new(Duration::from_secs(10)),
+        ))
+        .with_state(web_context)
+}
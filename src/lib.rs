@@ -1,8 +1,15 @@
+pub mod archive;
+pub mod check;
 pub mod config;
 pub mod consumer;
 pub mod crypto;
 pub mod errors;
+pub mod harness;
 pub mod http;
 pub mod matcher;
+pub mod metrics;
+pub mod prune;
+pub mod publish;
+pub mod reload;
 pub mod storage;
 pub mod vmc;
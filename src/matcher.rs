@@ -1,4 +1,7 @@
-use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashSet};
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
 use serde_json_path::JsonPath;
 
 use crate::config;
@@ -7,8 +10,15 @@ pub trait Matcher: Sync + Send {
     fn matches(&self, value: &serde_json::Value) -> bool;
 }
 
+/// Jetstream rejects subscriptions with more than this many wanted DIDs.
+const MAX_WANTED_DIDS: usize = 10_000;
+
 pub struct FeedMatcher {
     pub(crate) feed: String,
+    collections: HashSet<String>,
+    config_authors: HashSet<String>,
+    authors_file: Option<String>,
+    authors: Option<HashSet<String>>,
     matchers: Vec<Box<dyn Matcher>>,
 }
 
@@ -16,39 +26,211 @@ pub(crate) struct FeedMatchers(pub(crate) Vec<FeedMatcher>);
 
 impl FeedMatchers {
     pub(crate) fn from_config(config_feeds: &config::Feeds) -> Result<Self> {
-        let mut feed_matchers = vec![];
+        let feed_matchers = config_feeds
+            .feeds
+            .iter()
+            .map(FeedMatcher::from_config)
+            .collect::<Result<Vec<_>>>()?;
 
-        for config_feed in config_feeds.feeds.iter() {
-            let feed = config_feed.uri.clone();
+        Ok(Self(feed_matchers))
+    }
 
-            let mut matchers = vec![];
+    /// The union of collections that any feed is interested in.
+    pub(crate) fn wanted_collections(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|feed_matcher| feed_matcher.collections.iter().cloned())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect()
+    }
 
-            for config_feed_matcher in config_feed.matchers.iter() {
-                match config_feed_matcher {
-                    config::Matcher::Equal { path, value } => {
-                        matchers
-                            .push(Box::new(EqualsMatcher::new(value, path)?) as Box<dyn Matcher>);
-                    }
-                    config::Matcher::Prefix { path, value } => {
-                        matchers
-                            .push(Box::new(PrefixMatcher::new(value, path)?) as Box<dyn Matcher>);
-                    }
-                    config::Matcher::Sequence { path, values } => {
-                        matchers.push(Box::new(SequenceMatcher::new(values, path)?) as Box<dyn Matcher>);
-                    }
-                }
-            }
+    /// The union of authors when every feed is author-scoped. When any feed
+    /// considers all authors, or the union is too large for jetstream, `None`
+    /// is returned and events from every author are wanted.
+    pub(crate) fn wanted_dids(&self) -> Option<Vec<String>> {
+        let mut wanted_dids = BTreeSet::new();
+        for feed_matcher in self.0.iter() {
+            wanted_dids.extend(feed_matcher.authors.as_ref()?.iter().cloned());
+        }
 
-            feed_matchers.push(FeedMatcher { feed, matchers });
+        if wanted_dids.is_empty() || wanted_dids.len() > MAX_WANTED_DIDS {
+            return None;
         }
 
-        Ok(Self(feed_matchers))
+        Some(wanted_dids.into_iter().collect())
+    }
+
+    /// The feeds whose authors are read from a file, to be re-read with
+    /// [`read_authors`] without holding a lock on the matchers.
+    pub(crate) fn authors_sources(&self) -> Vec<AuthorsSource> {
+        self.0
+            .iter()
+            .filter_map(|feed_matcher| {
+                Some(AuthorsSource {
+                    feed: feed_matcher.feed.clone(),
+                    config_authors: feed_matcher.config_authors.clone(),
+                    authors_file: feed_matcher.authors_file.clone()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Swaps in re-read authors, returning true if any feed's authors changed.
+    pub(crate) fn apply_authors(&mut self, refreshed: Vec<RefreshedAuthors>) -> bool {
+        let mut changed = false;
+        for refreshed in refreshed {
+            let Some(feed_matcher) = self
+                .0
+                .iter_mut()
+                .find(|feed_matcher| feed_matcher.feed == refreshed.feed)
+            else {
+                continue;
+            };
+
+            if refreshed.authors != feed_matcher.authors {
+                tracing::info!(feed_id = ?feed_matcher.feed, "feed authors changed");
+                feed_matcher.authors = refreshed.authors;
+                changed = true;
+            }
+        }
+        changed
     }
 }
 
+pub(crate) struct AuthorsSource {
+    feed: String,
+    config_authors: HashSet<String>,
+    authors_file: String,
+}
+
+pub(crate) struct RefreshedAuthors {
+    feed: String,
+    authors: Option<HashSet<String>>,
+}
+
+/// Re-reads author list files. This blocks on file IO, so async callers run it
+/// with `spawn_blocking`. Feeds whose file cannot be read keep their authors.
+pub(crate) fn read_authors(sources: Vec<AuthorsSource>) -> Vec<RefreshedAuthors> {
+    sources
+        .into_iter()
+        .filter_map(
+            |source| match load_authors(&source.config_authors, Some(&source.authors_file)) {
+                Ok(authors) => Some(RefreshedAuthors {
+                    feed: source.feed,
+                    authors,
+                }),
+                Err(err) => {
+                    tracing::error!(error = ?err, feed_id = ?source.feed, "cannot refresh feed authors");
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+/// Combines the configured authors with the DIDs listed in the authors file,
+/// one per line. Blank lines and lines starting with `#` are ignored. `None`
+/// means the feed is not author-scoped.
+fn load_authors(
+    config_authors: &HashSet<String>,
+    authors_file: Option<&String>,
+) -> Result<Option<HashSet<String>>> {
+    let mut authors = config_authors.clone();
+
+    if let Some(authors_file) = authors_file {
+        let content = std::fs::read_to_string(authors_file)
+            .with_context(|| format!("reading authors file {} failed", authors_file))?;
+        authors.extend(
+            content
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| line.to_string()),
+        );
+    } else if authors.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(authors))
+}
+
+fn build_matcher(config_matcher: &config::Matcher) -> Result<Box<dyn Matcher>> {
+    let matcher: Box<dyn Matcher> = match config_matcher {
+        config::Matcher::Equal { path, value } => Box::new(EqualsMatcher::new(value, path)?),
+        config::Matcher::Prefix { path, value } => Box::new(PrefixMatcher::new(value, path)?),
+        config::Matcher::Sequence { path, values } => Box::new(SequenceMatcher::new(values, path)?),
+        config::Matcher::Regex { path, pattern } => Box::new(RegexMatcher::new(pattern, path)?),
+        config::Matcher::All { matchers } => Box::new(AllMatcher::new(build_matchers(
+            config_matcher.kind(),
+            matchers,
+        )?)),
+        config::Matcher::Any { matchers } => Box::new(AnyMatcher::new(build_matchers(
+            config_matcher.kind(),
+            matchers,
+        )?)),
+        config::Matcher::Not { matcher } => Box::new(NotMatcher::new(build_matcher(matcher)?)),
+    };
+    Ok(matcher)
+}
+
+/// Builds the nested matchers of an `all` or `any` matcher, which must have at
+/// least one.
+fn build_matchers(
+    kind: &str,
+    config_matchers: &[config::Matcher],
+) -> Result<Vec<Box<dyn Matcher>>> {
+    if config_matchers.is_empty() {
+        return Err(anyhow!("{} matcher must have at least one matcher", kind));
+    }
+    config_matchers.iter().map(build_matcher).collect()
+}
+
 impl FeedMatcher {
+    pub(crate) fn from_config(config_feed: &config::Feed) -> Result<Self> {
+        let feed = config_feed.uri.clone();
+
+        let matchers = config_feed
+            .matchers
+            .iter()
+            .map(build_matcher)
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("invalid matcher for feed {}", feed))?;
+
+        let collections = config_feed.collections.iter().cloned().collect();
+
+        let authors = load_authors(&config_feed.authors, config_feed.authors_file.as_ref())
+            .with_context(|| format!("invalid authors for feed {}", feed))?;
+
+        Ok(FeedMatcher {
+            feed,
+            collections,
+            config_authors: config_feed.authors.clone(),
+            authors_file: config_feed.authors_file.clone(),
+            authors,
+            matchers,
+        })
+    }
+
+    pub(crate) fn wants_collection(&self, collection: &str) -> bool {
+        self.collections.contains(collection)
+    }
+
+    pub(crate) fn wants_author(&self, did: &str) -> bool {
+        self.authors
+            .as_ref()
+            .is_none_or(|authors| authors.contains(did))
+    }
+
     pub(crate) fn matches(&self, value: &serde_json::Value) -> bool {
-        self.matchers.iter().any(|matcher| matcher.matches(value))
+        self.matched_by(value).is_some()
+    }
+
+    /// The index of the first configured matcher that matches the value.
+    pub(crate) fn matched_by(&self, value: &serde_json::Value) -> Option<usize> {
+        self.matchers
+            .iter()
+            .position(|matcher| matcher.matches(value))
     }
 }
 
@@ -180,6 +362,84 @@ impl Matcher for SequenceMatcher {
     }
 }
 
+/// Matches string values against a regular expression. Unlike the other string
+/// matchers the value is not lowercased, so patterns are case-sensitive unless
+/// they use the `(?i)` flag.
+pub struct RegexMatcher {
+    regex: Regex,
+    path: JsonPath,
+}
+
+impl RegexMatcher {
+    pub(crate) fn new(pattern: &str, path: &str) -> Result<Self> {
+        let regex = Regex::new(pattern).context("cannot parse pattern")?;
+        let path = JsonPath::parse(path).context("cannot parse path")?;
+        Ok(Self { regex, path })
+    }
+}
+
+impl Matcher for RegexMatcher {
+    fn matches(&self, value: &serde_json::Value) -> bool {
+        let nodes = self.path.query(value).all();
+
+        nodes.iter().any(|value| {
+            if let serde_json::Value::String(actual) = value {
+                self.regex.is_match(actual)
+            } else {
+                false
+            }
+        })
+    }
+}
+
+pub struct AllMatcher {
+    matchers: Vec<Box<dyn Matcher>>,
+}
+
+impl AllMatcher {
+    pub fn new(matchers: Vec<Box<dyn Matcher>>) -> Self {
+        Self { matchers }
+    }
+}
+
+impl Matcher for AllMatcher {
+    fn matches(&self, value: &serde_json::Value) -> bool {
+        self.matchers.iter().all(|matcher| matcher.matches(value))
+    }
+}
+
+pub struct AnyMatcher {
+    matchers: Vec<Box<dyn Matcher>>,
+}
+
+impl AnyMatcher {
+    pub fn new(matchers: Vec<Box<dyn Matcher>>) -> Self {
+        Self { matchers }
+    }
+}
+
+impl Matcher for AnyMatcher {
+    fn matches(&self, value: &serde_json::Value) -> bool {
+        self.matchers.iter().any(|matcher| matcher.matches(value))
+    }
+}
+
+pub struct NotMatcher {
+    matcher: Box<dyn Matcher>,
+}
+
+impl NotMatcher {
+    pub fn new(matcher: Box<dyn Matcher>) -> Self {
+        Self { matcher }
+    }
+}
+
+impl Matcher for NotMatcher {
+    fn matches(&self, value: &serde_json::Value) -> bool {
+        !self.matcher.matches(value)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -342,6 +602,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_vec, clippy::bool_assert_comparison)]
     fn sequence_matcher_edge_case_1() {
         let raw_json = r#"{"text": "Stellwerkstörung. Und Signalstörung.  Und der Alternativzug ist auch ausgefallen. Und überhaupt."}"#;
         let value: serde_json::Value = serde_json::from_str(raw_json).expect("json is valid");
@@ -350,4 +611,231 @@ mod tests {
                 .expect("matcher is valid");
         assert_eq!(matcher.matches(&value), false);
     }
+
+    #[test]
+    fn regex_matcher() {
+        let raw_json = r#"{
+    "did": "did:plc:tgudj2fjm77pzkuawquqhsxm",
+    "time_us": 1730491093829414,
+    "kind": "commit",
+    "commit": {
+        "rev": "3l7vxhiuibq2u",
+        "operation": "create",
+        "collection": "app.bsky.feed.post",
+        "rkey": "3l7vxhiu4kq2u",
+        "record": {
+            "$type": "app.bsky.feed.post",
+            "createdAt": "2024-11-01T19:58:12.980Z",
+            "langs": ["en"],
+            "text": "In Rust we trust",
+            "facets": [
+                {
+                    "features": [{"$type": "app.bsky.richtext.facet#tag", "tag": "rustlang"}],
+                    "index": { "byteEnd": 1, "byteStart": 0 }
+                }
+            ]
+        },
+        "cid": "bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"
+    }
+}"#;
+
+        let value: serde_json::Value = serde_json::from_str(raw_json).expect("json is valid");
+
+        let tests = vec![
+            ("$.commit.record.text", r"\bRust\b", true),
+            ("$.commit.record.text", r"\brust\b", false),
+            ("$.commit.record.text", r"(?i)\brust\b", true),
+            ("$.commit.record.text", r"\btrus\b", false),
+            ("$.commit.record.facets[*].features[*].tag", r"^rust", true),
+            ("$.commit.record.langs.*", r"^(de|fr)$", false),
+            ("$.time_us", r"\d+", false),
+            ("$.commit.record.notreal", r".*", false),
+        ];
+
+        for (path, pattern, result) in tests {
+            let matcher = RegexMatcher::new(pattern, path).expect("matcher is valid");
+            assert_eq!(matcher.matches(&value), result);
+        }
+
+        assert!(RegexMatcher::new(r"(unclosed", "$.commit.record.text").is_err());
+    }
+
+    #[test]
+    fn wanted_collections() {
+        let config_yaml = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/posts"
+  name: "Posts"
+  description: "Posts"
+  matchers: []
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/reposts"
+  name: "Reposts"
+  description: "Reposts"
+  collections: ["app.bsky.feed.repost", "app.bsky.feed.like", "app.bsky.feed.post"]
+  matchers: []
+"#;
+
+        let config_feeds: config::Feeds =
+            serde_yaml::from_str(config_yaml).expect("config is valid");
+        let feed_matchers = FeedMatchers::from_config(&config_feeds).expect("config is valid");
+
+        assert_eq!(
+            feed_matchers.wanted_collections(),
+            vec![
+                "app.bsky.feed.like".to_string(),
+                "app.bsky.feed.post".to_string(),
+                "app.bsky.feed.repost".to_string(),
+            ]
+        );
+        assert!(feed_matchers.0[0].wants_collection("app.bsky.feed.post"));
+        assert!(!feed_matchers.0[0].wants_collection("app.bsky.feed.like"));
+        assert!(feed_matchers.0[1].wants_collection("app.bsky.feed.like"));
+    }
+
+    #[test]
+    fn wanted_dids() {
+        let authors_file =
+            std::env::temp_dir().join(format!("supercell-authors-{}.txt", std::process::id()));
+        std::fs::write(
+            &authors_file,
+            "# community members\ndid:plc:cbkjy5n7bk3ax2wplmtjofq2\n\ndid:plc:fjr24tyxkpi3xqenws7anfmj\n",
+        )
+        .expect("authors file is written");
+
+        let config_yaml = format!(
+            r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/one"
+  name: "One"
+  description: "One"
+  authors: ["did:plc:4acsffvbo4niovge362ptijz"]
+  matchers: []
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/two"
+  name: "Two"
+  description: "Two"
+  authors_file: "{}"
+  matchers: []
+"#,
+            authors_file.display()
+        );
+
+        let mut config_feeds: config::Feeds =
+            serde_yaml::from_str(&config_yaml).expect("config is valid");
+        let mut feed_matchers = FeedMatchers::from_config(&config_feeds).expect("config is valid");
+
+        assert_eq!(
+            feed_matchers.wanted_dids(),
+            Some(vec![
+                "did:plc:4acsffvbo4niovge362ptijz".to_string(),
+                "did:plc:cbkjy5n7bk3ax2wplmtjofq2".to_string(),
+                "did:plc:fjr24tyxkpi3xqenws7anfmj".to_string(),
+            ])
+        );
+        assert!(feed_matchers.0[0].wants_author("did:plc:4acsffvbo4niovge362ptijz"));
+        assert!(!feed_matchers.0[0].wants_author("did:plc:cbkjy5n7bk3ax2wplmtjofq2"));
+        assert!(feed_matchers.0[1].wants_author("did:plc:cbkjy5n7bk3ax2wplmtjofq2"));
+
+        let refresh_authors = |feed_matchers: &mut FeedMatchers| {
+            feed_matchers.apply_authors(read_authors(feed_matchers.authors_sources()))
+        };
+        assert!(!refresh_authors(&mut feed_matchers));
+        std::fs::write(&authors_file, "did:plc:cbkjy5n7bk3ax2wplmtjofq2\n")
+            .expect("authors file is written");
+        assert!(refresh_authors(&mut feed_matchers));
+        assert!(!feed_matchers.0[1].wants_author("did:plc:fjr24tyxkpi3xqenws7anfmj"));
+
+        std::fs::remove_file(&authors_file).expect("authors file is removed");
+
+        config_feeds.feeds[1].authors_file = None;
+        let feed_matchers = FeedMatchers::from_config(&config_feeds).expect("config is valid");
+        assert_eq!(feed_matchers.wanted_dids(), None);
+        assert!(feed_matchers.0[1].wants_author("did:plc:cbkjy5n7bk3ax2wplmtjofq2"));
+    }
+
+    #[test]
+    fn boolean_matchers() {
+        let raw_json = r#"{
+    "did": "did:plc:tgudj2fjm77pzkuawquqhsxm",
+    "time_us": 1730491093829414,
+    "kind": "commit",
+    "commit": {
+        "rev": "3l7vxhiuibq2u",
+        "operation": "create",
+        "collection": "app.bsky.feed.post",
+        "rkey": "3l7vxhiu4kq2u",
+        "record": {
+            "$type": "app.bsky.feed.post",
+            "createdAt": "2024-11-01T19:58:12.980Z",
+            "langs": ["en"],
+            "text": "learning rust this weekend",
+            "facets": [
+                {
+                    "features": [{"$type": "app.bsky.richtext.facet#tag", "tag": "rust"}],
+                    "index": { "byteEnd": 1, "byteStart": 0 }
+                }
+            ]
+        },
+        "cid": "bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4"
+    }
+}"#;
+
+        let value: serde_json::Value = serde_json::from_str(raw_json).expect("json is valid");
+
+        let config_yaml = r#"
+- type: all
+  matchers:
+  - path: "$.commit.record.langs.*"
+    value: "en"
+    type: equal
+  - path: "$.commit.record.facets[*].features[*].tag"
+    value: "rust"
+    type: equal
+  - type: not
+    matcher:
+      path: "$.commit.record.reply.parent.uri"
+      value: "at://"
+      type: prefix
+- type: all
+  matchers:
+  - path: "$.commit.record.langs.*"
+    value: "de"
+    type: equal
+  - path: "$.commit.record.facets[*].features[*].tag"
+    value: "rust"
+    type: equal
+- type: any
+  matchers:
+  - path: "$.commit.record.langs.*"
+    value: "de"
+    type: equal
+  - path: "$.commit.record.text"
+    value: "learning"
+    type: prefix
+- type: not
+  matcher:
+    type: any
+    matchers:
+    - path: "$.commit.record.langs.*"
+      value: "en"
+      type: equal
+"#;
+
+        let config_matchers: Vec<config::Matcher> =
+            serde_yaml::from_str(config_yaml).expect("config is valid");
+
+        let tests = vec![true, false, true, false];
+        assert_eq!(config_matchers.len(), tests.len());
+
+        for (config_matcher, result) in config_matchers.iter().zip(tests) {
+            let matcher = build_matcher(config_matcher).expect("matcher is valid");
+            assert_eq!(matcher.matches(&value), result);
+        }
+
+        for kind in ["all", "any"] {
+            let config_matcher: config::Matcher =
+                serde_yaml::from_str(&format!("{{type: {}, matchers: []}}", kind))
+                    .expect("config is valid");
+            assert!(build_matcher(&config_matcher).is_err());
+        }
+    }
 }
@@ -0,0 +1,259 @@
+use anyhow::{Context, Result};
+use prometheus_client::encoding::text::encode;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::Registry;
+use std::sync::atomic::AtomicU64;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct FeedLabels {
+    pub feed: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct KindLabels {
+    pub kind: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct StageLabels {
+    pub stage: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RejectionLabels {
+    pub feed: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ResultLabels {
+    pub result: String,
+}
+
+/// Every metric supercell exports, registered with a single registry.
+pub struct Metrics {
+    registry: Registry,
+    pub consumer: ConsumerMetrics,
+    pub feed_skeleton: FeedSkeletonMetrics,
+    pub vmc: VmcMetrics,
+}
+
+/// Metrics describing the flow of events through the consumer pipeline. The
+/// backpressure counters record how often a stage found the channel to the
+/// next stage full and had to wait.
+#[derive(Clone, Default)]
+pub struct ConsumerMetrics {
+    pub connected: Gauge,
+    pub messages: Counter,
+    pub events: Counter,
+    pub decode_errors: Family<KindLabels, Counter>,
+    pub feed_matches: Family<FeedLabels, Counter>,
+    pub lag_seconds: Gauge<f64, AtomicU64>,
+    pub feed_content_writes: Counter,
+    pub write_batches: Counter,
+    pub write_retries: Counter,
+    pub backpressure: Family<StageLabels, Counter>,
+}
+
+#[derive(Clone)]
+pub struct FeedSkeletonMetrics {
+    pub duration_seconds: Family<FeedLabels, Histogram, fn() -> Histogram>,
+    pub rejections: Family<RejectionLabels, Counter>,
+}
+
+#[derive(Clone, Default)]
+pub struct VmcMetrics {
+    pub resolutions: Family<ResultLabels, Counter>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("supercell");
+
+        let consumer = ConsumerMetrics::default();
+        consumer.register(registry.sub_registry_with_prefix("consumer"));
+
+        let feed_skeleton = FeedSkeletonMetrics::default();
+        feed_skeleton.register(registry.sub_registry_with_prefix("feed_skeleton"));
+
+        let vmc = VmcMetrics::default();
+        vmc.register(registry.sub_registry_with_prefix("vmc"));
+
+        Self {
+            registry,
+            consumer,
+            feed_skeleton,
+            vmc,
+        }
+    }
+
+    /// Encodes every metric in the Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = String::new();
+        encode(&mut buffer, &self.registry).context("failed to encode metrics")?;
+        Ok(buffer)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConsumerMetrics {
+    fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "connected",
+            "Whether the consumer is connected to jetstream",
+            self.connected.clone(),
+        );
+        registry.register(
+            "messages",
+            "Messages read from jetstream",
+            self.messages.clone(),
+        );
+        registry.register(
+            "events",
+            "Events decoded from jetstream messages",
+            self.events.clone(),
+        );
+        registry.register(
+            "decode_errors",
+            "Messages that could not be read, decompressed, or parsed",
+            self.decode_errors.clone(),
+        );
+        registry.register(
+            "feed_matches",
+            "Events matched by each feed",
+            self.feed_matches.clone(),
+        );
+        registry.register(
+            "lag_seconds",
+            "Time between the newest decoded event and now",
+            self.lag_seconds.clone(),
+        );
+        registry.register(
+            "feed_content_writes",
+            "Feed content changes written to storage",
+            self.feed_content_writes.clone(),
+        );
+        registry.register(
+            "write_batches",
+            "Batches of feed content changes written to storage",
+            self.write_batches.clone(),
+        );
+        registry.register(
+            "write_retries",
+            "Failed batch writes that were retried",
+            self.write_retries.clone(),
+        );
+        registry.register(
+            "backpressure",
+            "Sends that waited for room in the channel to a pipeline stage",
+            self.backpressure.clone(),
+        );
+    }
+
+    pub(crate) fn stage_backpressure(&self, stage: &str) -> Counter {
+        self.backpressure
+            .get_or_create(&StageLabels {
+                stage: stage.to_string(),
+            })
+            .clone()
+    }
+
+    pub(crate) fn decode_error(&self, kind: &str) {
+        self.decode_errors
+            .get_or_create(&KindLabels {
+                kind: kind.to_string(),
+            })
+            .inc();
+    }
+}
+
+impl Default for FeedSkeletonMetrics {
+    fn default() -> Self {
+        Self {
+            duration_seconds: Family::new_with_constructor(|| {
+                Histogram::new(exponential_buckets(0.001, 2.0, 14))
+            }),
+            rejections: Family::default(),
+        }
+    }
+}
+
+impl FeedSkeletonMetrics {
+    fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "duration_seconds",
+            "Time taken to respond to feed skeleton requests",
+            self.duration_seconds.clone(),
+        );
+        registry.register(
+            "rejections",
+            "Feed skeleton requests denied because of a missing, invalid, or unlisted JWT",
+            self.rejections.clone(),
+        );
+    }
+
+    pub(crate) fn rejection(&self, feed: &str, reason: &str) {
+        self.rejections
+            .get_or_create(&RejectionLabels {
+                feed: feed.to_string(),
+                reason: reason.to_string(),
+            })
+            .inc();
+    }
+}
+
+impl VmcMetrics {
+    fn register(&self, registry: &mut Registry) {
+        registry.register(
+            "resolutions",
+            "DID document resolutions by result",
+            self.resolutions.clone(),
+        );
+    }
+
+    pub(crate) fn resolution(&self, result: &str) {
+        self.resolutions
+            .get_or_create(&ResultLabels {
+                result: result.to_string(),
+            })
+            .inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_metrics() {
+        let metrics = Metrics::new();
+        metrics.consumer.events.inc_by(3);
+        metrics.consumer.decode_error("parse");
+        metrics.feed_skeleton.rejection("at://feed", "invalid");
+        metrics
+            .feed_skeleton
+            .duration_seconds
+            .get_or_create(&FeedLabels {
+                feed: "at://feed".to_string(),
+            })
+            .observe(0.01);
+
+        let encoded = metrics.encode().expect("metrics are encoded");
+        assert!(encoded.contains("supercell_consumer_events_total 3"));
+        assert!(encoded.contains(r#"supercell_consumer_decode_errors_total{kind="parse"} 1"#));
+        assert!(encoded.contains(
+            r#"supercell_feed_skeleton_rejections_total{feed="at://feed",reason="invalid"} 1"#
+        ));
+        assert!(encoded
+            .contains(r#"supercell_feed_skeleton_duration_seconds_count{feed="at://feed"} 1"#));
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::sync::watch;
+use tokio::time::{sleep, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::config;
+use crate::storage::{
+    feed_content_expire, feed_content_truncate, feed_interaction_expire, StoragePool,
+};
+
+pub struct FeedContentPruneTask {
+    pool: StoragePool,
+    feeds: watch::Receiver<config::Feeds>,
+    interaction_max_age: Duration,
+    cancellation_token: CancellationToken,
+}
+
+impl FeedContentPruneTask {
+    pub fn new(
+        pool: StoragePool,
+        feeds: watch::Receiver<config::Feeds>,
+        interaction_max_age: Duration,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        Self {
+            pool,
+            feeds,
+            interaction_max_age,
+            cancellation_token,
+        }
+    }
+
+    pub async fn run_background(&self, interval: Duration) -> Result<()> {
+        let sleeper = sleep(interval);
+        tokio::pin!(sleeper);
+
+        loop {
+            tokio::select! {
+                () = self.cancellation_token.cancelled() => {
+                    break;
+                },
+                () = &mut sleeper => {
+                    if let Err(err) = self.main().await {
+                        tracing::error!("FeedContentPruneTask task failed: {}", err);
+                    }
+                    sleeper.as_mut().reset(Instant::now() + interval);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn main(&self) -> Result<()> {
+        let feeds = self.feeds.borrow().clone();
+        let now = chrono::Utc::now().timestamp_micros();
+
+        for feed in &feeds.feeds {
+            if let Some(max_age) = &feed.max_age {
+                let max_age = i64::try_from(max_age.as_ref().as_micros()).unwrap_or(i64::MAX);
+                let deleted =
+                    feed_content_expire(&self.pool, &feed.uri, now.saturating_sub(max_age)).await?;
+                if deleted > 0 {
+                    tracing::info!(feed_id = ?feed.uri, deleted = ?deleted, "expired feed content");
+                }
+            }
+
+            if let Some(max_items) = feed.max_items {
+                let deleted = feed_content_truncate(&self.pool, &feed.uri, max_items).await?;
+                if deleted > 0 {
+                    tracing::info!(feed_id = ?feed.uri, deleted = ?deleted, "truncated feed content");
+                }
+            }
+        }
+
+        let interaction_max_age =
+            chrono::Duration::from_std(self.interaction_max_age).unwrap_or(chrono::Duration::MAX);
+        let updated_before = chrono::Utc::now()
+            .checked_sub_signed(interaction_max_age)
+            .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+        let deleted = feed_interaction_expire(&self.pool, updated_before).await?;
+        if deleted > 0 {
+            tracing::info!(deleted = ?deleted, "expired feed interactions");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,448 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config;
+
+const FEED_GENERATOR_COLLECTION: &str = "app.bsky.feed.generator";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+    access_jwt: String,
+    did: String,
+}
+
+#[derive(Deserialize)]
+struct UploadedBlob {
+    blob: Value,
+}
+
+#[derive(Deserialize)]
+struct PutRecordOutput {
+    uri: String,
+}
+
+#[derive(Deserialize)]
+struct GetRecordOutput {
+    value: Value,
+}
+
+#[derive(Deserialize)]
+struct XrpcError {
+    error: Option<String>,
+    message: Option<String>,
+}
+
+/// An account on a PDS that feed generator records are published to.
+pub struct PublishAccount {
+    pub pds: String,
+    pub identifier: String,
+    pub password: String,
+}
+
+/// Creates or updates the `app.bsky.feed.generator` record of every feed owned
+/// by the account. The record key is taken from the feed URI, so publishing
+/// again updates the existing record, keeping its `createdAt` and, unless a
+/// new one is given, its avatar. Feeds owned by other accounts are skipped.
+/// Returns the URIs of the records written.
+pub async fn publish_feeds(
+    http_client: &reqwest::Client,
+    account: &PublishAccount,
+    external_base: &str,
+    feeds: &config::Feeds,
+    avatar: Option<&str>,
+) -> Result<Vec<String>> {
+    let pds = account.pds.trim_end_matches('/');
+    let session = create_session(http_client, pds, account).await?;
+
+    let avatar = match avatar {
+        Some(path) => Some(upload_blob(http_client, pds, &session, path).await?),
+        None => None,
+    };
+
+    let mut published = Vec::new();
+    for feed in &feeds.feeds {
+        let (repo, rkey) = feed_record_key(&feed.uri)?;
+        if repo != session.did {
+            tracing::warn!(feed_id = ?feed.uri, did = ?session.did, "feed is not owned by the account, skipping");
+            continue;
+        }
+
+        let existing = get_record(http_client, pds, &session, rkey).await?;
+        let created_at = existing
+            .as_ref()
+            .and_then(|existing| existing.get("createdAt"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+            });
+
+        let mut record = json!({
+            "$type": FEED_GENERATOR_COLLECTION,
+            "did": format!("did:web:{}", external_base),
+            "displayName": feed.name,
+            "description": feed.description,
+            "createdAt": created_at,
+        });
+        let avatar = avatar.clone().or_else(|| {
+            existing
+                .as_ref()
+                .and_then(|existing| existing.get("avatar"))
+                .cloned()
+        });
+        if let Some(avatar) = avatar {
+            record["avatar"] = avatar;
+        }
+
+        let output: PutRecordOutput = xrpc_response(
+            http_client
+                .post(format!("{}/xrpc/com.atproto.repo.putRecord", pds))
+                .bearer_auth(&session.access_jwt)
+                .json(&json!({
+                    "repo": session.did,
+                    "collection": FEED_GENERATOR_COLLECTION,
+                    "rkey": rkey,
+                    "record": record,
+                }))
+                .send()
+                .await?,
+        )
+        .await
+        .with_context(|| format!("unable to publish feed {}", feed.uri))?;
+
+        published.push(output.uri);
+    }
+
+    Ok(published)
+}
+
+/// Splits an `at://{did}/app.bsky.feed.generator/{rkey}` URI into its DID and
+/// record key.
+fn feed_record_key(uri: &str) -> Result<(&str, &str)> {
+    uri.strip_prefix("at://")
+        .and_then(|rest| rest.split_once('/'))
+        .and_then(|(repo, rest)| {
+            rest.strip_prefix(FEED_GENERATOR_COLLECTION)
+                .and_then(|rest| rest.strip_prefix('/'))
+                .filter(|rkey| !rkey.is_empty() && !rkey.contains('/'))
+                .map(|rkey| (repo, rkey))
+        })
+        .ok_or(anyhow!(
+            "feed {} is not an app.bsky.feed.generator URI",
+            uri
+        ))
+}
+
+async fn create_session(
+    http_client: &reqwest::Client,
+    pds: &str,
+    account: &PublishAccount,
+) -> Result<Session> {
+    xrpc_response(
+        http_client
+            .post(format!("{}/xrpc/com.atproto.server.createSession", pds))
+            .json(&json!({
+                "identifier": account.identifier,
+                "password": account.password,
+            }))
+            .send()
+            .await?,
+    )
+    .await
+    .context("unable to log in")
+}
+
+async fn upload_blob(
+    http_client: &reqwest::Client,
+    pds: &str,
+    session: &Session,
+    path: &str,
+) -> Result<Value> {
+    let mime_type = match path.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()) {
+        Some(ext) if ext == "png" => "image/png",
+        Some(ext) if ext == "jpg" || ext == "jpeg" => "image/jpeg",
+        _ => return Err(anyhow!("avatar {} must be a png or jpeg image", path)),
+    };
+    let data = std::fs::read(path).with_context(|| format!("unable to read avatar {}", path))?;
+
+    let uploaded: UploadedBlob = xrpc_response(
+        http_client
+            .post(format!("{}/xrpc/com.atproto.repo.uploadBlob", pds))
+            .bearer_auth(&session.access_jwt)
+            .header(reqwest::header::CONTENT_TYPE, mime_type)
+            .body(data)
+            .send()
+            .await?,
+    )
+    .await
+    .context("unable to upload avatar")?;
+
+    Ok(uploaded.blob)
+}
+
+async fn get_record(
+    http_client: &reqwest::Client,
+    pds: &str,
+    session: &Session,
+    rkey: &str,
+) -> Result<Option<Value>> {
+    let response = http_client
+        .get(format!("{}/xrpc/com.atproto.repo.getRecord", pds))
+        .bearer_auth(&session.access_jwt)
+        .query(&[
+            ("repo", session.did.as_str()),
+            ("collection", FEED_GENERATOR_COLLECTION),
+            ("rkey", rkey),
+        ])
+        .send()
+        .await?;
+
+    if response.status() == reqwest::StatusCode::BAD_REQUEST {
+        let error: XrpcError = response.json().await?;
+        return match error.error.as_deref() {
+            Some("RecordNotFound") => Ok(None),
+            _ => Err(anyhow!(
+                "unable to get existing feed generator record: {} {}",
+                error.error.unwrap_or_default(),
+                error.message.unwrap_or_default()
+            )),
+        };
+    }
+
+    let output: GetRecordOutput = xrpc_response(response).await?;
+    Ok(Some(output.value))
+}
+
+async fn xrpc_response<T: serde::de::DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response.json().await?);
+    }
+
+    let error: Option<XrpcError> = response.json().await.ok();
+    let (error, message) = error
+        .map(|error| (error.error, error.message))
+        .unwrap_or_default();
+    Err(anyhow!(
+        "{}: {} {}",
+        status,
+        error.unwrap_or_default(),
+        message.unwrap_or_default()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        body::Bytes,
+        extract::{Query, State},
+        http::{HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+
+    const DID: &str = "did:plc:4acsffvbo4niovge362ptijz";
+    const FEEDS_YAML: &str = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/3la5azib4xe2c"
+  name: "Smoke Signal Support"
+  description: "The Smoke Signal Support feed."
+  matchers: []
+- uri: "at://did:plc:cbkjy5n7bk3ax2wplmtjofq2/app.bsky.feed.generator/other"
+  name: "Someone Else"
+  description: "A feed owned by another account."
+  matchers: []
+"#;
+
+    #[derive(Clone, Default)]
+    struct MockPds {
+        records: Arc<Mutex<HashMap<String, Value>>>,
+        blobs: Arc<Mutex<Vec<(String, usize)>>>,
+    }
+
+    fn authorized(headers: &HeaderMap) -> bool {
+        headers
+            .get("authorization")
+            .is_some_and(|value| value == "Bearer access-token")
+    }
+
+    async fn create_session(Json(body): Json<Value>) -> impl IntoResponse {
+        if body["identifier"] == "smokesignal.events" && body["password"] == "app-password" {
+            Json(json!({"accessJwt": "access-token", "refreshJwt": "refresh-token", "did": DID, "handle": "smokesignal.events"})).into_response()
+        } else {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "AuthenticationRequired", "message": "Invalid identifier or password"})),
+            )
+                .into_response()
+        }
+    }
+
+    async fn upload_blob(
+        State(pds): State<MockPds>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> impl IntoResponse {
+        if !authorized(&headers) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        let mime_type = headers
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        pds.blobs
+            .lock()
+            .unwrap()
+            .push((mime_type.clone(), body.len()));
+        Json(json!({"blob": {"$type": "blob", "ref": {"$link": "bafkrei"}, "mimeType": mime_type, "size": body.len()}})).into_response()
+    }
+
+    async fn get_record(
+        State(pds): State<MockPds>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> impl IntoResponse {
+        match pds.records.lock().unwrap().get(&query["rkey"]) {
+            Some(value) => Json(json!({"uri": "", "value": value})).into_response(),
+            None => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "RecordNotFound"})),
+            )
+                .into_response(),
+        }
+    }
+
+    async fn put_record(
+        State(pds): State<MockPds>,
+        headers: HeaderMap,
+        Json(body): Json<Value>,
+    ) -> impl IntoResponse {
+        if !authorized(&headers) || body["repo"] != DID {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        let rkey = body["rkey"].as_str().unwrap().to_string();
+        let uri = format!(
+            "at://{}/{}/{}",
+            DID,
+            body["collection"].as_str().unwrap(),
+            rkey
+        );
+        pds.records
+            .lock()
+            .unwrap()
+            .insert(rkey, body["record"].clone());
+        Json(json!({"uri": uri, "cid": "bafyrei"})).into_response()
+    }
+
+    async fn serve(pds: MockPds) -> String {
+        let router = Router::new()
+            .route(
+                "/xrpc/com.atproto.server.createSession",
+                post(create_session),
+            )
+            .route("/xrpc/com.atproto.repo.uploadBlob", post(upload_blob))
+            .route("/xrpc/com.atproto.repo.getRecord", get(get_record))
+            .route("/xrpc/com.atproto.repo.putRecord", post(put_record))
+            .with_state(pds);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{}", address)
+    }
+
+    fn account(pds: String, password: &str) -> PublishAccount {
+        PublishAccount {
+            pds,
+            identifier: "smokesignal.events".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn publish_to_mock_pds() -> Result<()> {
+        let pds = MockPds::default();
+        let account = account(serve(pds.clone()).await, "app-password");
+        let feeds: config::Feeds = serde_yaml::from_str(FEEDS_YAML)?;
+        let http_client = reqwest::Client::new();
+
+        let avatar =
+            std::env::temp_dir().join(format!("supercell-avatar-{}.png", std::process::id()));
+        std::fs::write(&avatar, b"not really a png")?;
+
+        let published = publish_feeds(
+            &http_client,
+            &account,
+            "feeds.example.com",
+            &feeds,
+            avatar.to_str(),
+        )
+        .await;
+        std::fs::remove_file(&avatar)?;
+
+        assert_eq!(
+            published?,
+            vec!["at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/3la5azib4xe2c"]
+        );
+        assert_eq!(
+            pds.blobs.lock().unwrap().as_slice(),
+            &[("image/png".to_string(), 16)]
+        );
+
+        let record = pds.records.lock().unwrap()["3la5azib4xe2c"].clone();
+        assert_eq!(record["$type"], "app.bsky.feed.generator");
+        assert_eq!(record["did"], "did:web:feeds.example.com");
+        assert_eq!(record["displayName"], "Smoke Signal Support");
+        assert_eq!(record["description"], "The Smoke Signal Support feed.");
+        assert_eq!(record["avatar"]["mimeType"], "image/png");
+
+        // Publishing again updates the same record and keeps its createdAt and
+        // avatar.
+        let published =
+            publish_feeds(&http_client, &account, "feeds.example.com", &feeds, None).await?;
+        assert_eq!(published.len(), 1);
+
+        let records = pds.records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records["3la5azib4xe2c"]["createdAt"], record["createdAt"]);
+        assert_eq!(records["3la5azib4xe2c"]["avatar"], record["avatar"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn publish_login_failure() -> Result<()> {
+        let account = account(serve(MockPds::default()).await, "wrong-password");
+        let feeds: config::Feeds = serde_yaml::from_str(FEEDS_YAML)?;
+
+        let err = publish_feeds(
+            &reqwest::Client::new(),
+            &account,
+            "feeds.example.com",
+            &feeds,
+            None,
+        )
+        .await
+        .expect_err("login fails");
+        assert!(format!("{:#}", err).contains("Invalid identifier or password"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn record_key() {
+        assert_eq!(
+            feed_record_key("at://did:plc:abc/app.bsky.feed.generator/hot").unwrap(),
+            ("did:plc:abc", "hot")
+        );
+        assert!(feed_record_key("at://did:plc:abc/app.bsky.feed.post/hot").is_err());
+        assert!(feed_record_key("did:plc:abc/app.bsky.feed.generator/hot").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::check::validate_feeds;
+use crate::config;
+use crate::http::context::WebContext;
+
+pub struct FeedsReloadTask {
+    feeds_path: String,
+    web_context: WebContext,
+    feeds: watch::Sender<config::Feeds>,
+    cancellation_token: CancellationToken,
+}
+
+impl FeedsReloadTask {
+    pub fn new(
+        feeds_path: String,
+        web_context: WebContext,
+        feeds: watch::Sender<config::Feeds>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        Self {
+            feeds_path,
+            web_context,
+            feeds,
+            cancellation_token,
+        }
+    }
+
+    pub async fn run_background(&self) -> Result<()> {
+        let mut hangup =
+            signal(SignalKind::hangup()).context("failed to install SIGHUP handler")?;
+
+        loop {
+            tokio::select! {
+                () = self.cancellation_token.cancelled() => {
+                    break;
+                },
+                _ = hangup.recv() => {
+                    if let Err(err) = self.reload().await {
+                        tracing::error!(error = ?err, "feeds reload failed, keeping current feeds");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses and validates the feeds file, with the same checks as
+    /// `check-config`, before swapping it into the web context and every task
+    /// subscribed to feed changes. The current feeds are kept if the file is
+    /// invalid.
+    pub async fn reload(&self) -> Result<()> {
+        let feeds: config::Feeds = self.feeds_path.clone().try_into()?;
+
+        validate_feeds(&feeds)?;
+
+        self.web_context.update_feeds(&feeds).await;
+        self.feeds.send_replace(feeds);
+
+        tracing::info!(path = ?self.feeds_path, "feeds reloaded");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::*;
+    use crate::http::handle_get_feed_skeleton::tests::web_context;
+    use crate::storage::StoragePool;
+
+    const FEEDS_YAML: &str = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/first"
+  name: "First"
+  description: "The first feed."
+  matchers: []
+"#;
+
+    const RELOADED_FEEDS_YAML: &str = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/second"
+  name: "Second"
+  description: "The second feed."
+  allow: ["did:plc:cbkjy5n7bk3ax2wplmtjofq2"]
+  matchers:
+  - path: "$.commit.record.text"
+    value: "dnd"
+    type: equal
+"#;
+
+    const INVALID_FEEDS_YAML: &str = r#"
+feeds:
+- uri: "at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/third"
+  name: "Third"
+  description: "A feed with an allow entry that is not a DID."
+  allow: ["alice.example.com"]
+  matchers: []
+"#;
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn reload_feeds(pool: SqlitePool) -> Result<()> {
+        let pool = StoragePool::from(pool);
+        let web_context = web_context(pool, "http://localhost".to_string(), FEEDS_YAML);
+
+        let feeds_path =
+            std::env::temp_dir().join(format!("supercell-reload-{}.yml", std::process::id()));
+        let (feeds_sender, mut feeds_receiver) =
+            watch::channel(serde_yaml::from_str::<config::Feeds>(FEEDS_YAML)?);
+        let task = FeedsReloadTask::new(
+            feeds_path.to_string_lossy().to_string(),
+            web_context.clone(),
+            feeds_sender,
+            CancellationToken::new(),
+        );
+
+        std::fs::write(&feeds_path, RELOADED_FEEDS_YAML)?;
+        task.reload().await?;
+
+        let snapshot = web_context.feeds().await;
+        let uris = |snapshot: &crate::http::context::FeedsSnapshot| {
+            snapshot
+                .descriptions
+                .iter()
+                .map(|description| description.uri.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            uris(&snapshot),
+            vec!["at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/second"]
+        );
+        assert_eq!(snapshot.controls.len(), 1);
+        assert!(snapshot.controls
+            ["at://did:plc:4acsffvbo4niovge362ptijz/app.bsky.feed.generator/second"]
+            .allowed
+            .contains("did:plc:cbkjy5n7bk3ax2wplmtjofq2"));
+        assert!(feeds_receiver.has_changed()?);
+        assert_eq!(feeds_receiver.borrow_and_update().feeds[0].name, "Second");
+
+        // An invalid file is rejected and the reloaded feeds are kept.
+        std::fs::write(&feeds_path, INVALID_FEEDS_YAML)?;
+        let result = task.reload().await;
+        std::fs::remove_file(&feeds_path)?;
+
+        let err = result.expect_err("invalid feeds are rejected");
+        assert!(format!("{}", err).contains("allow entry alice.example.com is not a DID"));
+        assert_eq!(uris(&*web_context.feeds().await), uris(&snapshot));
+        assert!(!feeds_receiver.has_changed()?);
+
+        Ok(())
+    }
+}
@@ -1,10 +1,76 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use chrono::{prelude::*, Duration};
-use sqlx::{Pool, Sqlite};
+use sqlx::{Pool, Postgres, Sqlite};
 
 use model::FeedContent;
 
-pub type StoragePool = Pool<Sqlite>;
+/// A connection pool for one of the supported database backends.
+///
+/// Queries are written once with `$N` placeholders, which both SQLite and
+/// PostgreSQL accept, and run against whichever pool is in use.
+#[derive(Clone)]
+pub enum StoragePool {
+    Sqlite(Pool<Sqlite>),
+    Postgres(Pool<Postgres>),
+}
+
+macro_rules! with_pool {
+    ($pool:expr, |$inner:ident| $body:expr) => {
+        match $pool {
+            StoragePool::Sqlite($inner) => $body,
+            StoragePool::Postgres($inner) => $body,
+        }
+    };
+}
+
+impl StoragePool {
+    /// Connects to the database named by `database_url`, selecting the backend
+    /// from its scheme.
+    pub async fn connect(database_url: &str) -> Result<Self> {
+        let scheme = database_url.split_once(':').map(|(scheme, _)| scheme);
+        match scheme {
+            Some("sqlite") => Ok(Self::Sqlite(
+                Pool::<Sqlite>::connect(database_url)
+                    .await
+                    .context("failed to connect to sqlite database")?,
+            )),
+            Some("postgres" | "postgresql") => Ok(Self::Postgres(
+                Pool::<Postgres>::connect(database_url)
+                    .await
+                    .context("failed to connect to postgres database")?,
+            )),
+            _ => Err(anyhow!(
+                "unsupported DATABASE_URL scheme, expected sqlite or postgres"
+            )),
+        }
+    }
+
+    pub async fn migrate(&self) -> Result<()> {
+        match self {
+            Self::Sqlite(pool) => sqlx::migrate!("migrations/sqlite").run(pool).await,
+            Self::Postgres(pool) => sqlx::migrate!("migrations/postgres").run(pool).await,
+        }
+        .context("failed to run migrations")
+    }
+}
+
+impl From<Pool<Sqlite>> for StoragePool {
+    fn from(pool: Pool<Sqlite>) -> Self {
+        Self::Sqlite(pool)
+    }
+}
+
+impl From<Pool<Postgres>> for StoragePool {
+    fn from(pool: Pool<Postgres>) -> Self {
+        Self::Postgres(pool)
+    }
+}
+
+const FEED_CONTENT_INSERT: &str = "INSERT INTO feed_content (feed_id, uri, indexed_at, cid, updated_at) VALUES ($1, $2, $3, $4, $5) ON CONFLICT(feed_id, uri) DO UPDATE SET indexed_at = excluded.indexed_at, cid = excluded.cid, updated_at = excluded.updated_at";
+const FEED_CONTENT_UPSERT: &str = "INSERT INTO feed_content (feed_id, uri, indexed_at, cid, updated_at) VALUES ($1, $2, $3, $4, $5) ON CONFLICT(feed_id, uri) DO UPDATE SET cid = excluded.cid, updated_at = excluded.updated_at";
+const FEED_CONTENT_DELETE: &str = "DELETE FROM feed_content WHERE feed_id = $1 AND uri = $2";
+const FEED_CONTENT_DELETE_URI: &str = "DELETE FROM feed_content WHERE uri = $1";
+const CONSUMER_CONTROL_INSERT: &str = "INSERT INTO consumer_control (source, time_us, updated_at) VALUES ($1, $2, $3) ON CONFLICT(source) DO UPDATE SET time_us = excluded.time_us, updated_at = excluded.updated_at";
 
 pub mod model {
     use serde::Serialize;
@@ -17,25 +83,180 @@ pub mod model {
         pub indexed_at: i64,
         pub cid: String,
     }
+
+    /// The atproto verification method of a DID, along with the PDS and handle
+    /// from the same DID document.
+    #[derive(Clone, Debug, PartialEq, FromRow)]
+    pub struct VerificationMethod {
+        pub did: String,
+        pub multikey: String,
+        pub pds: Option<String>,
+        pub handle: Option<String>,
+    }
+
+    /// Feedback a viewer sent about a post with `app.bsky.feed.sendInteractions`.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum InteractionEvent {
+        Seen,
+        RequestLess,
+        RequestMore,
+    }
+
+    impl InteractionEvent {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                InteractionEvent::Seen => "seen",
+                InteractionEvent::RequestLess => "requestLess",
+                InteractionEvent::RequestMore => "requestMore",
+            }
+        }
+
+        /// The event that this one replaces, as a viewer can change their
+        /// mind about seeing more or less of a post.
+        pub fn opposite(&self) -> Option<Self> {
+            match self {
+                InteractionEvent::Seen => None,
+                InteractionEvent::RequestLess => Some(InteractionEvent::RequestMore),
+                InteractionEvent::RequestMore => Some(InteractionEvent::RequestLess),
+            }
+        }
+    }
+
+    /// A change to feed content, applied in order with other changes by
+    /// `feed_content_write_batch`.
+    #[derive(Clone)]
+    pub enum FeedContentWrite {
+        Insert(FeedContent),
+        Upsert(FeedContent),
+        Delete { feed_id: String, uri: String },
+        DeleteUri(String),
+    }
 }
 
 pub async fn feed_content_insert(
     pool: &StoragePool,
     feed_content: &model::FeedContent,
 ) -> Result<()> {
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let now = Utc::now();
+        sqlx::query(FEED_CONTENT_INSERT)
+            .bind(&feed_content.feed_id)
+            .bind(&feed_content.uri)
+            .bind(feed_content.indexed_at)
+            .bind(&feed_content.cid)
+            .bind(now)
+            .execute(tx.as_mut())
+            .await
+            .context("failed to insert feed content record")?;
+
+        tx.commit().await.context("failed to commit transaction")
+    })
+}
+
+pub async fn feed_content_upsert(
+    pool: &StoragePool,
+    feed_content: &model::FeedContent,
+) -> Result<()> {
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let now = Utc::now();
+        sqlx::query(FEED_CONTENT_UPSERT)
+            .bind(&feed_content.feed_id)
+            .bind(&feed_content.uri)
+            .bind(feed_content.indexed_at)
+            .bind(&feed_content.cid)
+            .bind(now)
+            .execute(tx.as_mut())
+            .await
+            .context("failed to upsert feed content record")?;
+
+        tx.commit().await.context("failed to commit transaction")
+    })
+}
+
+pub async fn feed_content_delete(pool: &StoragePool, feed_id: &str, uri: &str) -> Result<()> {
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        sqlx::query(FEED_CONTENT_DELETE)
+            .bind(feed_id)
+            .bind(uri)
+            .execute(tx.as_mut())
+            .await
+            .context("failed to delete feed content record")?;
+
+        tx.commit().await.context("failed to commit transaction")
+    })
+}
 
+pub async fn feed_content_delete_uri(pool: &StoragePool, uri: &str) -> Result<()> {
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        sqlx::query(FEED_CONTENT_DELETE_URI)
+            .bind(uri)
+            .execute(tx.as_mut())
+            .await
+            .context("failed to delete feed content records")?;
+
+        tx.commit().await.context("failed to commit transaction")
+    })
+}
+
+/// Applies feed content changes in order and, when given, stores the consumer
+/// cursor in the same transaction so that the two never disagree.
+pub async fn feed_content_write_batch(
+    pool: &StoragePool,
+    writes: &[model::FeedContentWrite],
+    cursor: Option<(&str, i64)>,
+) -> Result<()> {
     let now = Utc::now();
-    sqlx::query("INSERT OR REPLACE INTO feed_content (feed_id, uri, indexed_at, cid, updated_at) VALUES (?, ?, ?, ?, ?)")
-        .bind(&feed_content.feed_id)
-        .bind(&feed_content.uri)
-        .bind(feed_content.indexed_at)
-        .bind(&feed_content.cid)
-        .bind(now)
-        .execute(tx.as_mut())
-        .await.context("failed to insert feed content record")?;
 
-    tx.commit().await.context("failed to commit transaction")
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        for write in writes {
+            let query = match write {
+                model::FeedContentWrite::Insert(feed_content) => sqlx::query(FEED_CONTENT_INSERT)
+                    .bind(&feed_content.feed_id)
+                    .bind(&feed_content.uri)
+                    .bind(feed_content.indexed_at)
+                    .bind(&feed_content.cid)
+                    .bind(now),
+                model::FeedContentWrite::Upsert(feed_content) => sqlx::query(FEED_CONTENT_UPSERT)
+                    .bind(&feed_content.feed_id)
+                    .bind(&feed_content.uri)
+                    .bind(feed_content.indexed_at)
+                    .bind(&feed_content.cid)
+                    .bind(now),
+                model::FeedContentWrite::Delete { feed_id, uri } => {
+                    sqlx::query(FEED_CONTENT_DELETE).bind(feed_id).bind(uri)
+                }
+                model::FeedContentWrite::DeleteUri(uri) => {
+                    sqlx::query(FEED_CONTENT_DELETE_URI).bind(uri)
+                }
+            };
+            query
+                .execute(tx.as_mut())
+                .await
+                .context("failed to write feed content record")?;
+        }
+
+        if let Some((source, time_us)) = cursor {
+            sqlx::query(CONSUMER_CONTROL_INSERT)
+                .bind(source)
+                .bind(time_us)
+                .bind(now)
+                .execute(tx.as_mut())
+                .await
+                .context("failed to update consumer control record")?;
+        }
+
+        tx.commit().await.context("failed to commit transaction")
+    })
 }
 
 pub async fn feed_content_paginate(
@@ -44,140 +265,308 @@ pub async fn feed_content_paginate(
     limit: Option<u16>,
     cursor: Option<(i64, String)>,
 ) -> Result<Vec<FeedContent>> {
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+    let limit = i64::from(limit.unwrap_or(20).clamp(1, 100));
+
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let results = if let Some((indexed_at, cid)) = cursor {
+            let query = "SELECT * FROM feed_content WHERE feed_id = $1 AND (indexed_at, cid) < ($2, $3) ORDER BY indexed_at DESC, cid DESC LIMIT $4";
+
+            sqlx::query_as::<_, FeedContent>(query)
+                .bind(feed_uri)
+                .bind(indexed_at)
+                .bind(cid)
+                .bind(limit)
+                .fetch_all(tx.as_mut())
+                .await?
+        } else {
+            let query = "SELECT * FROM feed_content WHERE feed_id = $1 ORDER BY indexed_at DESC, cid DESC LIMIT $2";
+
+            sqlx::query_as::<_, FeedContent>(query)
+                .bind(feed_uri)
+                .bind(limit)
+                .fetch_all(tx.as_mut())
+                .await?
+        };
 
-    let limit = limit.unwrap_or(20).clamp(1, 100);
+        tx.commit().await.context("failed to commit transaction")?;
 
-    let results = if let Some((indexed_at, cid)) = cursor {
-        let query = "SELECT * FROM feed_content WHERE feed_id = ? AND (indexed_at, cid) < (?, ?) ORDER BY indexed_at DESC, cid DESC LIMIT ?";
+        Ok(results)
+    })
+}
 
-        sqlx::query_as::<_, FeedContent>(query)
-            .bind(feed_uri)
-            .bind(indexed_at)
-            .bind(cid)
-            .bind(limit)
-            .fetch_all(tx.as_mut())
-            .await?
-    } else {
-        let query = "SELECT * FROM feed_content WHERE feed_id = ? ORDER BY indexed_at DESC, cid DESC LIMIT ?";
-
-        sqlx::query_as::<_, FeedContent>(query)
-            .bind(feed_uri)
-            .bind(limit)
-            .fetch_all(tx.as_mut())
-            .await?
-    };
+/// Like `feed_content_paginate`, but skips posts the viewer has seen or asked
+/// to see less of.
+pub async fn feed_content_paginate_unseen(
+    pool: &StoragePool,
+    feed_uri: &str,
+    viewer: &str,
+    limit: Option<u16>,
+    cursor: Option<(i64, String)>,
+) -> Result<Vec<FeedContent>> {
+    let limit = i64::from(limit.unwrap_or(20).clamp(1, 100));
+
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let results = if let Some((indexed_at, cid)) = cursor {
+            let query = "SELECT * FROM feed_content WHERE feed_id = $1 AND NOT EXISTS (SELECT 1 FROM feed_interaction WHERE feed_interaction.viewer = $2 AND feed_interaction.uri = feed_content.uri AND feed_interaction.event IN ('seen', 'requestLess')) AND (indexed_at, cid) < ($3, $4) ORDER BY indexed_at DESC, cid DESC LIMIT $5";
+
+            sqlx::query_as::<_, FeedContent>(query)
+                .bind(feed_uri)
+                .bind(viewer)
+                .bind(indexed_at)
+                .bind(cid)
+                .bind(limit)
+                .fetch_all(tx.as_mut())
+                .await?
+        } else {
+            let query = "SELECT * FROM feed_content WHERE feed_id = $1 AND NOT EXISTS (SELECT 1 FROM feed_interaction WHERE feed_interaction.viewer = $2 AND feed_interaction.uri = feed_content.uri AND feed_interaction.event IN ('seen', 'requestLess')) ORDER BY indexed_at DESC, cid DESC LIMIT $3";
+
+            sqlx::query_as::<_, FeedContent>(query)
+                .bind(feed_uri)
+                .bind(viewer)
+                .bind(limit)
+                .fetch_all(tx.as_mut())
+                .await?
+        };
+
+        tx.commit().await.context("failed to commit transaction")?;
+
+        Ok(results)
+    })
+}
+
+/// Records a viewer's interactions in one transaction. Recording `requestMore`
+/// or `requestLess` for a post replaces the opposite request. Interactions
+/// with posts that are not in any feed are ignored, and only the viewer's
+/// `max_interactions` most recent interactions are kept.
+pub async fn feed_interaction_insert(
+    pool: &StoragePool,
+    viewer: &str,
+    interactions: &[(String, model::InteractionEvent)],
+    max_interactions: i64,
+) -> Result<()> {
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let now = Utc::now();
+        for (uri, event) in interactions {
+            if let Some(opposite) = event.opposite() {
+                sqlx::query(
+                    "DELETE FROM feed_interaction WHERE viewer = $1 AND uri = $2 AND event = $3",
+                )
+                .bind(viewer)
+                .bind(uri)
+                .bind(opposite.as_str())
+                .execute(tx.as_mut())
+                .await
+                .context("failed to delete feed interaction")?;
+            }
+
+            sqlx::query(
+                "INSERT INTO feed_interaction (viewer, uri, event, updated_at) SELECT $1, $2, $3, $4 WHERE EXISTS (SELECT 1 FROM feed_content WHERE uri = $2) ON CONFLICT(viewer, uri, event) DO UPDATE SET updated_at = excluded.updated_at",
+            )
+            .bind(viewer)
+            .bind(uri)
+            .bind(event.as_str())
+            .bind(now)
+            .execute(tx.as_mut())
+            .await
+            .context("failed to insert feed interaction")?;
+        }
+
+        sqlx::query(
+            "DELETE FROM feed_interaction WHERE viewer = $1 AND (uri, event) NOT IN (SELECT uri, event FROM feed_interaction WHERE viewer = $1 ORDER BY updated_at DESC LIMIT $2)",
+        )
+        .bind(viewer)
+        .bind(max_interactions)
+        .execute(tx.as_mut())
+        .await
+        .context("failed to delete excess feed interactions")?;
 
-    tx.commit().await.context("failed to commit transaction")?;
+        tx.commit().await.context("failed to commit transaction")
+    })
+}
+
+/// Deletes interactions last recorded before `updated_before`.
+pub async fn feed_interaction_expire(
+    pool: &StoragePool,
+    updated_before: DateTime<Utc>,
+) -> Result<u64> {
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let deleted = sqlx::query("DELETE FROM feed_interaction WHERE updated_at < $1")
+            .bind(updated_before)
+            .execute(tx.as_mut())
+            .await
+            .context("failed to delete expired feed interactions")?
+            .rows_affected();
+
+        tx.commit().await.context("failed to commit transaction")?;
 
-    Ok(results)
+        Ok(deleted)
+    })
 }
 
 pub async fn consumer_control_insert(pool: &StoragePool, source: &str, time_us: i64) -> Result<()> {
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
 
-    let now = Utc::now();
-    sqlx::query(
-        "INSERT OR REPLACE INTO consumer_control (source, time_us, updated_at) VALUES (?, ?, ?)",
-    )
-    .bind(source)
-    .bind(time_us)
-    .bind(now)
-    .execute(tx.as_mut())
-    .await?;
+        let now = Utc::now();
+        sqlx::query(CONSUMER_CONTROL_INSERT)
+            .bind(source)
+            .bind(time_us)
+            .bind(now)
+            .execute(tx.as_mut())
+            .await?;
 
-    tx.commit().await.context("failed to commit transaction")
+        tx.commit().await.context("failed to commit transaction")
+    })
 }
 
 pub async fn consumer_control_get(pool: &StoragePool, source: &str) -> Result<Option<i64>> {
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
 
-    let result =
-        sqlx::query_scalar::<_, i64>("SELECT time_us FROM consumer_control WHERE source = ?")
-            .bind(source)
-            .fetch_optional(tx.as_mut())
-            .await
-            .context("failed to select consumer control record")?;
+        let result =
+            sqlx::query_scalar::<_, i64>("SELECT time_us FROM consumer_control WHERE source = $1")
+                .bind(source)
+                .fetch_optional(tx.as_mut())
+                .await
+                .context("failed to select consumer control record")?;
 
-    tx.commit().await.context("failed to commit transaction")?;
+        tx.commit().await.context("failed to commit transaction")?;
 
-    Ok(result)
+        Ok(result)
+    })
 }
 
 pub async fn verifcation_method_insert(
     pool: &StoragePool,
-    did: &str,
-    multikey: &str,
+    verification_method: &model::VerificationMethod,
 ) -> Result<()> {
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
-
-    let now = Utc::now();
-    sqlx::query(
-        "INSERT OR REPLACE INTO verification_method_cache (did, multikey, updated_at) VALUES (?, ?, ?)",
-    )
-    .bind(did)
-    .bind(multikey)
-    .bind(now)
-    .execute(tx.as_mut())
-        .await.context("failed to update verification method cache")?;
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let now = Utc::now();
+        sqlx::query(
+            "INSERT INTO verification_method_cache (did, multikey, pds, handle, updated_at) VALUES ($1, $2, $3, $4, $5) ON CONFLICT(did) DO UPDATE SET multikey = excluded.multikey, pds = excluded.pds, handle = excluded.handle, updated_at = excluded.updated_at",
+        )
+        .bind(&verification_method.did)
+        .bind(&verification_method.multikey)
+        .bind(&verification_method.pds)
+        .bind(&verification_method.handle)
+        .bind(now)
+        .execute(tx.as_mut())
+            .await.context("failed to update verification method cache")?;
 
-    tx.commit().await.context("failed to commit transaction")
+        tx.commit().await.context("failed to commit transaction")
+    })
 }
 
 pub async fn verification_method_cleanup(pool: &StoragePool) -> Result<()> {
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
-
     let now = Utc::now();
     let seven_days_ago = now - Duration::days(7);
-    sqlx::query("DELETE FROM verification_method_cache WHERE updated_at < ?")
-        .bind(seven_days_ago)
-        .execute(tx.as_mut())
-        .await
-        .context("failed to delete old verification method cache records")?;
 
-    tx.commit().await.context("failed to commit transaction")
-}
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
 
-pub async fn verification_method_get(pool: &StoragePool, did: &str) -> Result<Option<String>> {
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
+        sqlx::query("DELETE FROM verification_method_cache WHERE updated_at < $1")
+            .bind(seven_days_ago)
+            .execute(tx.as_mut())
+            .await
+            .context("failed to delete old verification method cache records")?;
 
-    let result = sqlx::query_scalar::<_, String>(
-        "SELECT multikey FROM verification_method_cache WHERE did = ?",
-    )
-    .bind(did)
-    .fetch_optional(tx.as_mut())
-    .await
-    .context("failed to select verification method cache record")?;
-    tx.commit().await.context("failed to commit transaction")?;
-    Ok(result)
+        tx.commit().await.context("failed to commit transaction")
+    })
 }
 
-pub async fn feed_content_truncate(pool: &StoragePool, feed_id: &str) -> Result<()> {
-    let mut tx = pool.begin().await.context("failed to begin transaction")?;
-
-    let result = sqlx::query_scalar::<_, DateTime<Utc>>("SELECT updated_at FROM feed_content WHERE feed_id = ? ORDER BY indexed_at DESC, indexed_at_more DESC LIMIT 1 OFFSET 501")
-        .bind(feed_id)
+pub async fn verification_method_get(
+    pool: &StoragePool,
+    did: &str,
+) -> Result<Option<model::VerificationMethod>> {
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let result = sqlx::query_as::<_, model::VerificationMethod>(
+            "SELECT did, multikey, pds, handle FROM verification_method_cache WHERE did = $1",
+        )
+        .bind(did)
         .fetch_optional(tx.as_mut())
-        .await.context("failed select feed content mark record")?;
+        .await
+        .context("failed to select verification method cache record")?;
+        tx.commit().await.context("failed to commit transaction")?;
+        Ok(result)
+    })
+}
 
-    if let Some(updated_at) = result {
-        sqlx::query("DELETE FROM feed_content WHERE feed_id = ? AND updated_at < ?")
+pub async fn feed_content_truncate(
+    pool: &StoragePool,
+    feed_id: &str,
+    max_items: u32,
+) -> Result<u64> {
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let mark = sqlx::query_as::<_, (i64, String)>("SELECT indexed_at, cid FROM feed_content WHERE feed_id = $1 ORDER BY indexed_at DESC, cid DESC LIMIT 1 OFFSET $2")
             .bind(feed_id)
-            .bind(updated_at)
+            .bind(i64::from(max_items))
+            .fetch_optional(tx.as_mut())
+            .await.context("failed select feed content mark record")?;
+
+        let mut deleted = 0;
+        if let Some((indexed_at, cid)) = mark {
+            deleted = sqlx::query(
+                "DELETE FROM feed_content WHERE feed_id = $1 AND (indexed_at, cid) <= ($2, $3)",
+            )
+            .bind(feed_id)
+            .bind(indexed_at)
+            .bind(cid)
             .execute(tx.as_mut())
             .await
-            .context("failed to delete feed content beyond mark")?;
-    }
+            .context("failed to delete feed content beyond mark")?
+            .rows_affected();
+        }
+
+        tx.commit().await.context("failed to commit transaction")?;
 
-    tx.commit().await.context("failed to commit transaction")
+        Ok(deleted)
+    })
+}
+
+pub async fn feed_content_expire(
+    pool: &StoragePool,
+    feed_id: &str,
+    indexed_before: i64,
+) -> Result<u64> {
+    with_pool!(pool, |pool| {
+        let mut tx = pool.begin().await.context("failed to begin transaction")?;
+
+        let deleted =
+            sqlx::query("DELETE FROM feed_content WHERE feed_id = $1 AND indexed_at < $2")
+                .bind(feed_id)
+                .bind(indexed_before)
+                .execute(tx.as_mut())
+                .await
+                .context("failed to delete expired feed content")?
+                .rows_affected();
+
+        tx.commit().await.context("failed to commit transaction")?;
+
+        Ok(deleted)
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use sqlx::SqlitePool;
 
-    #[sqlx::test]
+    #[sqlx::test(migrations = "migrations/sqlite")]
     async fn record_feed_content(pool: SqlitePool) -> sqlx::Result<()> {
+        let pool = super::StoragePool::from(pool);
         let record = super::model::FeedContent {
             feed_id: "feed".to_string(),
             uri: "at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/3la3bqjg4hx2n"
@@ -204,8 +593,177 @@ mod tests {
         Ok(())
     }
 
-    #[sqlx::test]
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn update_and_delete_feed_content(pool: SqlitePool) -> sqlx::Result<()> {
+        let pool = super::StoragePool::from(pool);
+        let uri = "at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/3la3bqjg4hx2n";
+        for feed_id in ["feed1", "feed2"] {
+            let record = super::model::FeedContent {
+                feed_id: feed_id.to_string(),
+                uri: uri.to_string(),
+                indexed_at: 1730673934229172_i64,
+                cid: "bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74".to_string(),
+            };
+            super::feed_content_insert(&pool, &record)
+                .await
+                .expect("failed to insert record");
+        }
+
+        let updated = super::model::FeedContent {
+            feed_id: "feed1".to_string(),
+            uri: uri.to_string(),
+            indexed_at: 1730673934229999_i64,
+            cid: "bafyreide7jpu67vvkn4p2iznph6frbwv6vamt7yg5duppqjqggz4sdfik4".to_string(),
+        };
+        super::feed_content_upsert(&pool, &updated)
+            .await
+            .expect("failed to upsert record");
+
+        let records = super::feed_content_paginate(&pool, "feed1", None, None)
+            .await
+            .expect("failed to paginate records");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].cid, updated.cid);
+        assert_eq!(records[0].indexed_at, 1730673934229172_i64);
+
+        super::feed_content_delete(&pool, "feed1", uri)
+            .await
+            .expect("failed to delete record");
+        assert!(super::feed_content_paginate(&pool, "feed1", None, None)
+            .await
+            .expect("failed to paginate records")
+            .is_empty());
+        assert_eq!(
+            super::feed_content_paginate(&pool, "feed2", None, None)
+                .await
+                .expect("failed to paginate records")
+                .len(),
+            1
+        );
+
+        super::feed_content_delete_uri(&pool, uri)
+            .await
+            .expect("failed to delete records");
+        assert!(super::feed_content_paginate(&pool, "feed2", None, None)
+            .await
+            .expect("failed to paginate records")
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn truncate_and_expire_feed_content(pool: SqlitePool) -> sqlx::Result<()> {
+        truncate_and_expire(&super::StoragePool::from(pool)).await;
+        Ok(())
+    }
+
+    async fn truncate_and_expire(pool: &super::StoragePool) {
+        for (feed_id, indexed_at) in [
+            ("feed1", 1),
+            ("feed1", 2),
+            ("feed1", 3),
+            ("feed1", 4),
+            ("feed2", 1),
+        ] {
+            let record = super::model::FeedContent {
+                feed_id: feed_id.to_string(),
+                uri: format!(
+                    "at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/{indexed_at}"
+                ),
+                indexed_at,
+                cid: "bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74".to_string(),
+            };
+            super::feed_content_insert(pool, &record)
+                .await
+                .expect("failed to insert record");
+        }
+
+        assert_eq!(
+            super::feed_content_truncate(pool, "feed1", 5)
+                .await
+                .expect("failed to truncate records"),
+            0
+        );
+        assert_eq!(
+            super::feed_content_truncate(pool, "feed1", 3)
+                .await
+                .expect("failed to truncate records"),
+            1
+        );
+        assert_eq!(
+            super::feed_content_expire(pool, "feed1", 3)
+                .await
+                .expect("failed to expire records"),
+            1
+        );
+
+        let records = super::feed_content_paginate(pool, "feed1", None, None)
+            .await
+            .expect("failed to paginate records");
+        assert_eq!(
+            records.iter().map(|r| r.indexed_at).collect::<Vec<_>>(),
+            vec![4, 3]
+        );
+        assert_eq!(
+            super::feed_content_paginate(pool, "feed2", None, None)
+                .await
+                .expect("failed to paginate records")
+                .len(),
+            1
+        );
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn write_batch(pool: SqlitePool) -> sqlx::Result<()> {
+        let pool = super::StoragePool::from(pool);
+        let feed_content = |feed_id: &str, rkey: &str| super::model::FeedContent {
+            feed_id: feed_id.to_string(),
+            uri: format!("at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/{rkey}"),
+            indexed_at: 1730673934229172_i64,
+            cid: "bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74".to_string(),
+        };
+
+        super::feed_content_write_batch(
+            &pool,
+            &[
+                super::model::FeedContentWrite::Insert(feed_content("feed1", "a")),
+                super::model::FeedContentWrite::Insert(feed_content("feed1", "b")),
+                super::model::FeedContentWrite::Insert(feed_content("feed2", "b")),
+                super::model::FeedContentWrite::Delete {
+                    feed_id: "feed1".to_string(),
+                    uri: feed_content("feed1", "a").uri,
+                },
+                super::model::FeedContentWrite::DeleteUri(feed_content("feed2", "b").uri),
+                super::model::FeedContentWrite::Upsert(feed_content("feed2", "c")),
+            ],
+            Some(("foo", 1730673934229172_i64)),
+        )
+        .await
+        .expect("failed to write batch");
+
+        for (feed_id, expected) in [("feed1", 0), ("feed2", 1)] {
+            assert_eq!(
+                super::feed_content_paginate(&pool, feed_id, None, None)
+                    .await
+                    .expect("failed to paginate records")
+                    .len(),
+                expected
+            );
+        }
+        assert_eq!(
+            super::consumer_control_get(&pool, "foo")
+                .await
+                .expect("failed to get record"),
+            Some(1730673934229172_i64)
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
     async fn consumer_control(pool: SqlitePool) -> sqlx::Result<()> {
+        let pool = super::StoragePool::from(pool);
         super::consumer_control_insert(&pool, "foo", 1730673934229172_i64)
             .await
             .expect("failed to insert record");
@@ -230,4 +788,158 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn unseen_feed_content(pool: SqlitePool) -> sqlx::Result<()> {
+        unseen_and_expire(&super::StoragePool::from(pool)).await;
+        Ok(())
+    }
+
+    async fn unseen_and_expire(pool: &super::StoragePool) {
+        use super::model::InteractionEvent;
+
+        let uri =
+            |rkey: i64| format!("at://did:plc:qadlgs4xioohnhi2jg54mqds/app.bsky.feed.post/{rkey}");
+        for indexed_at in 1..=4 {
+            let record = super::model::FeedContent {
+                feed_id: "feed".to_string(),
+                uri: uri(indexed_at),
+                indexed_at,
+                cid: "bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74".to_string(),
+            };
+            super::feed_content_insert(pool, &record)
+                .await
+                .expect("failed to insert record");
+        }
+
+        super::feed_interaction_insert(
+            pool,
+            "did:plc:viewer",
+            &[
+                (uri(4), InteractionEvent::Seen),
+                (uri(3), InteractionEvent::RequestLess),
+                (uri(2), InteractionEvent::RequestMore),
+            ],
+            100,
+        )
+        .await
+        .expect("failed to insert interactions");
+
+        let unseen = |viewer: &'static str, cursor: Option<(i64, String)>| {
+            let pool = pool.clone();
+            async move {
+                super::feed_content_paginate_unseen(&pool, "feed", viewer, None, cursor)
+                    .await
+                    .expect("failed to paginate records")
+                    .into_iter()
+                    .map(|record| record.indexed_at)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(unseen("did:plc:viewer", None).await, vec![2, 1]);
+        assert_eq!(
+            unseen(
+                "did:plc:viewer",
+                Some((
+                    2,
+                    "bafyreih74qdc6zskq7yarqi3xm634vnubf4g3ac5ieegbvakprxpjnsj74".to_string()
+                ))
+            )
+            .await,
+            vec![1]
+        );
+        assert_eq!(unseen("did:plc:other", None).await, vec![4, 3, 2, 1]);
+
+        // Asking to see more of a post replaces asking to see less of it.
+        super::feed_interaction_insert(
+            pool,
+            "did:plc:viewer",
+            &[(uri(3), InteractionEvent::RequestMore)],
+            100,
+        )
+        .await
+        .expect("failed to insert interactions");
+        assert_eq!(unseen("did:plc:viewer", None).await, vec![3, 2, 1]);
+
+        assert_eq!(
+            super::feed_interaction_expire(pool, chrono::Utc::now() + chrono::Duration::hours(1))
+                .await
+                .expect("failed to expire interactions"),
+            3
+        );
+        assert_eq!(unseen("did:plc:viewer", None).await, vec![4, 3, 2, 1]);
+
+        // Interactions with posts in no feed are not stored, and only the most
+        // recent interactions of each viewer are kept.
+        super::feed_interaction_insert(
+            pool,
+            "did:plc:viewer",
+            &[
+                (uri(5), InteractionEvent::Seen),
+                (uri(4), InteractionEvent::Seen),
+            ],
+            2,
+        )
+        .await
+        .expect("failed to insert interactions");
+        super::feed_interaction_insert(
+            pool,
+            "did:plc:viewer",
+            &[
+                (uri(3), InteractionEvent::Seen),
+                (uri(2), InteractionEvent::Seen),
+            ],
+            2,
+        )
+        .await
+        .expect("failed to insert interactions");
+        assert_eq!(unseen("did:plc:viewer", None).await, vec![4, 1]);
+        assert_eq!(
+            super::feed_interaction_expire(pool, chrono::Utc::now() + chrono::Duration::hours(1))
+                .await
+                .expect("failed to expire interactions"),
+            2
+        );
+    }
+
+    /// Runs the queries with backend specific SQL against the PostgreSQL
+    /// database at `POSTGRES_TEST_DATABASE_URL`, in a schema dropped afterwards.
+    #[tokio::test]
+    #[ignore = "requires POSTGRES_TEST_DATABASE_URL"]
+    async fn postgres_queries() -> anyhow::Result<()> {
+        use std::str::FromStr;
+
+        use anyhow::Context;
+        use sqlx::postgres::{PgConnectOptions, PgPool};
+
+        let database_url = std::env::var("POSTGRES_TEST_DATABASE_URL")
+            .context("POSTGRES_TEST_DATABASE_URL must be set")?;
+
+        let schema = format!("supercell_test_{}", std::process::id());
+        let admin = PgPool::connect(&database_url).await?;
+        sqlx::query(&format!("DROP SCHEMA IF EXISTS {schema} CASCADE"))
+            .execute(&admin)
+            .await?;
+        sqlx::query(&format!("CREATE SCHEMA {schema}"))
+            .execute(&admin)
+            .await?;
+
+        let options =
+            PgConnectOptions::from_str(&database_url)?.options([("search_path", schema.as_str())]);
+        let pool = super::StoragePool::from(PgPool::connect_with(options).await?);
+        pool.migrate().await?;
+
+        truncate_and_expire(&pool).await;
+        unseen_and_expire(&pool).await;
+
+        if let super::StoragePool::Postgres(pool) = pool {
+            pool.close().await;
+        }
+        sqlx::query(&format!("DROP SCHEMA {schema} CASCADE"))
+            .execute(&admin)
+            .await?;
+
+        Ok(())
+    }
 }
@@ -1,31 +1,59 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, PoisonError};
 
 use anyhow::{anyhow, Result};
 use chrono::Duration;
 use serde::Deserialize;
+use tokio::sync::watch;
 use tokio::time::{sleep, Instant};
 use tokio_util::sync::CancellationToken;
 
-use crate::storage::{verifcation_method_insert, verification_method_cleanup, StoragePool};
+use crate::config;
+use crate::metrics::VmcMetrics;
+use crate::storage::{
+    model, verifcation_method_insert, verification_method_cleanup, verification_method_get,
+    StoragePool,
+};
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct VerificationMethod {
-    #[serde(rename = "publicKeyMultibase")]
-    public_key_multibase: String,
+    id: String,
+    #[serde(rename = "type", default)]
+    method_type: String,
+    public_key_multibase: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Service {
+    id: String,
+    #[serde(rename = "type", default)]
+    service_type: String,
+    service_endpoint: serde_json::Value,
 }
 
+/// The parts of a DID document, from either PLC or did:web, that are used to
+/// verify a DID's signatures. Verification methods and services other than
+/// the atproto ones are ignored, whatever their shape.
 #[derive(Deserialize)]
-struct ResolvedPlcDid {
+#[serde(rename_all = "camelCase")]
+struct DidDocument {
     id: String,
-    #[serde(rename = "verificationMethod")]
+    #[serde(default)]
+    also_known_as: Vec<String>,
+    #[serde(default)]
     verification_method: Vec<VerificationMethod>,
+    #[serde(default)]
+    service: Vec<Service>,
 }
 
 pub struct VerificationMethodCacheTask {
     pool: StoragePool,
     http_client: reqwest::Client,
     plc_hostname: String,
-    dids: HashSet<String>,
+    feeds: watch::Receiver<config::Feeds>,
+    metrics: VmcMetrics,
     cancellation_token: CancellationToken,
 }
 
@@ -34,19 +62,21 @@ impl VerificationMethodCacheTask {
         pool: StoragePool,
         http_client: reqwest::Client,
         plc_hostname: String,
-        dids: HashSet<String>,
+        feeds: watch::Receiver<config::Feeds>,
+        metrics: VmcMetrics,
         cancellation_token: CancellationToken,
     ) -> Self {
         Self {
             pool,
             http_client,
             plc_hostname,
-            dids,
+            feeds,
+            metrics,
             cancellation_token,
         }
     }
 
-    pub async fn run_background(&self, interval: Duration) -> Result<()> {
+    pub async fn run_background(&mut self, interval: Duration) -> Result<()> {
         let interval = interval.to_std()?;
 
         let sleeper = sleep(interval);
@@ -57,6 +87,11 @@ impl VerificationMethodCacheTask {
             () = self.cancellation_token.cancelled() => {
                 break;
             },
+            Ok(()) = self.feeds.changed() => {
+                if let Err(err) = self.main().await {
+                    tracing::error!("StatsTask task failed: {}", err);
+                }
+            },
             () = &mut sleeper => {
 
                     if let Err(err) = self.main().await {
@@ -72,46 +107,618 @@ impl VerificationMethodCacheTask {
     }
 
     pub async fn main(&self) -> Result<()> {
-        for did in &self.dids {
-            let query_response = self.plc_query(did).await;
+        let dids = self
+            .feeds
+            .borrow()
+            .feeds
+            .iter()
+            .flat_map(|feed| feed.allow.iter().cloned())
+            .collect::<HashSet<String>>();
+
+        for did in &dids {
+            let query_response =
+                resolve_verification_method(&self.http_client, &self.plc_hostname, did).await;
             if let Err(err) = query_response {
                 tracing::error!(error = ?err, "Failed to query PLC for DID: {}", did);
+                self.metrics.resolution("error");
                 continue;
             }
-            let key = query_response.unwrap();
+            self.metrics.resolution("ok");
+            let verification_method = query_response.unwrap();
 
-            verifcation_method_insert(&self.pool, did, &key).await?;
+            verifcation_method_insert(&self.pool, &verification_method).await?;
         }
 
         verification_method_cleanup(&self.pool).await?;
         Ok(())
     }
+}
+
+/// Resolves the DID document of `did`, from the PLC directory or, for
+/// `did:web`, the hostname's `/.well-known/did.json`, and returns its atproto
+/// verification method. `plc_hostname` may include a scheme, otherwise https
+/// is used.
+pub async fn resolve_verification_method(
+    http_client: &reqwest::Client,
+    plc_hostname: &str,
+    did: &str,
+) -> Result<model::VerificationMethod> {
+    let url = if let Some(hostname) = did.strip_prefix("did:web:") {
+        format!(
+            "https://{}/.well-known/did.json",
+            did_web_hostname(hostname)?
+        )
+    } else if plc_hostname.contains("://") {
+        format!("{}/{}", plc_hostname.trim_end_matches('/'), did)
+    } else {
+        format!("https://{}/{}", plc_hostname, did)
+    };
+
+    let document: DidDocument = http_client
+        .get(url)
+        .timeout(Duration::seconds(10).to_std()?)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    atproto_verification_method(did, &document)
+}
+
+/// Checks that a did:web hostname is a public DNS name. DIDs come from
+/// unauthenticated JWTs, so IP literals, ports, paths, and local or private
+/// names are rejected to keep requests from reaching internal services.
+fn did_web_hostname(hostname: &str) -> Result<&str> {
+    if hostname.parse::<std::net::IpAddr>().is_ok() {
+        return Err(anyhow!("did:web hostname {} is an IP address", hostname));
+    }
+
+    let labels = hostname.split('.').collect::<Vec<_>>();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    };
+    if hostname.len() > 253 || labels.len() < 2 || !labels.iter().all(valid_label) {
+        return Err(anyhow!("did:web hostname {} is not a DNS name", hostname));
+    }
+
+    let tld = labels[labels.len() - 1];
+    if tld.chars().all(|c| c.is_ascii_digit())
+        || [
+            "localhost",
+            "local",
+            "internal",
+            "lan",
+            "home",
+            "arpa",
+            "test",
+            "invalid",
+        ]
+        .contains(&tld)
+    {
+        return Err(anyhow!("did:web hostname {} is not public", hostname));
+    }
+
+    Ok(hostname)
+}
+
+fn atproto_verification_method(
+    did: &str,
+    document: &DidDocument,
+) -> Result<model::VerificationMethod> {
+    if document.id != did {
+        return Err(anyhow!("DID mismatch"));
+    }
+
+    // Fragment ids may be relative to the document or include the DID.
+    let is_fragment = |id: &str, fragment: &str| {
+        id.strip_suffix(fragment)
+            .is_some_and(|prefix| prefix.is_empty() || prefix == did)
+    };
+
+    let method = document
+        .verification_method
+        .iter()
+        .find(|method| is_fragment(&method.id, "#atproto"))
+        .ok_or(anyhow!("No atproto verification method found"))?;
+    let public_key_multibase = method.public_key_multibase.as_deref().ok_or(anyhow!(
+        "atproto verification method has no publicKeyMultibase"
+    ))?;
+
+    let pds = document
+        .service
+        .iter()
+        .find(|service| {
+            is_fragment(&service.id, "#atproto_pds")
+                && service.service_type == "AtprotoPersonalDataServer"
+        })
+        .and_then(|service| service.service_endpoint.as_str())
+        .map(str::to_string);
+
+    let handle = document
+        .also_known_as
+        .iter()
+        .find_map(|aka| aka.strip_prefix("at://"))
+        .map(str::to_string);
+
+    Ok(model::VerificationMethod {
+        did: did.to_string(),
+        multikey: multikey(&method.method_type, public_key_multibase)?,
+        pds,
+        handle,
+    })
+}
+
+/// Converts a verification method's public key to a multikey. `Multikey`
+/// methods already are one, while the legacy types hold a bare public key
+/// without a multicodec prefix, which is compressed and prefixed here.
+fn multikey(method_type: &str, public_key_multibase: &str) -> Result<String> {
+    let (prefix, public_key) = match method_type {
+        "Multikey" => return Ok(public_key_multibase.to_string()),
+        "EcdsaSecp256k1VerificationKey2019" => {
+            let (_, key) = multibase::decode(public_key_multibase)?;
+            let key = k256::PublicKey::from_sec1_bytes(&key)?;
+            (
+                [0xe7, 0x01],
+                k256::elliptic_curve::sec1::ToEncodedPoint::to_encoded_point(&key, true)
+                    .as_bytes()
+                    .to_vec(),
+            )
+        }
+        "EcdsaSecp256r1VerificationKey2019" => {
+            let (_, key) = multibase::decode(public_key_multibase)?;
+            let key = p256::PublicKey::from_sec1_bytes(&key)?;
+            (
+                [0x80, 0x24],
+                p256::elliptic_curve::sec1::ToEncodedPoint::to_encoded_point(&key, true)
+                    .as_bytes()
+                    .to_vec(),
+            )
+        }
+        _ => {
+            return Err(anyhow!(
+                "unsupported verification method type {:?}",
+                method_type
+            ))
+        }
+    };
+
+    Ok(multibase::encode(
+        multibase::Base::Base58Btc,
+        [prefix.as_slice(), &public_key].concat(),
+    ))
+}
+
+/// Resolves verification methods on demand for JWT issuers that are not in
+/// the cache. Resolutions of unknown DIDs and refreshes of cached ones are
+/// rate limited separately, so that requests naming arbitrary issuers cannot
+/// stop key rotations of known viewers from being picked up. Failures are
+/// remembered for a while so that unresolvable DIDs are not fetched on every
+/// request, and a DID can only be refreshed once a minute.
+pub struct DidResolver {
+    pool: StoragePool,
+    http_client: reqwest::Client,
+    plc_hostname: String,
+    rate_limit: u32,
+    negative_cache_ttl: std::time::Duration,
+    metrics: VmcMetrics,
+    state: Mutex<DidResolverState>,
+}
+
+#[derive(Default)]
+struct DidResolverState {
+    unknown: RateLimitWindow,
+    refresh: RateLimitWindow,
+    attempts: HashMap<String, (Instant, bool)>,
+}
+
+#[derive(Default)]
+struct RateLimitWindow {
+    start: Option<Instant>,
+    count: u32,
+}
+
+impl RateLimitWindow {
+    /// Counts an attempt, returning false if `limit` attempts were already
+    /// made in the current window.
+    fn admit(&mut self, now: Instant, limit: u32) -> bool {
+        match self.start {
+            Some(start) if now.duration_since(start) < RATE_LIMIT_WINDOW => {}
+            _ => {
+                self.start = Some(now);
+                self.count = 0;
+            }
+        }
+        if self.count >= limit {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+const RATE_LIMIT_WINDOW: std::time::Duration = std::time::Duration::from_secs(60);
+const REFRESH_MIN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+impl DidResolver {
+    /// `rate_limit` is the number of resolutions of unknown DIDs, and separately
+    /// of refreshes, allowed per minute. Zero disables on-demand resolution so
+    /// only cached keys are used.
+    pub fn new(
+        pool: StoragePool,
+        http_client: reqwest::Client,
+        plc_hostname: String,
+        rate_limit: u32,
+        negative_cache_ttl: std::time::Duration,
+        metrics: VmcMetrics,
+    ) -> Self {
+        Self {
+            pool,
+            http_client,
+            plc_hostname,
+            rate_limit,
+            negative_cache_ttl,
+            metrics,
+            state: Mutex::new(DidResolverState::default()),
+        }
+    }
+
+    /// Returns the cached verification method of `did`, resolving and caching
+    /// it if there is none.
+    pub async fn verification_method(&self, did: &str) -> Result<String> {
+        if let Some(verification_method) = verification_method_get(&self.pool, did).await? {
+            return Ok(verification_method.multikey);
+        }
+        self.resolve(did, false).await
+    }
+
+    /// Resolves `did` again, replacing its cached verification method. Used
+    /// when a signature fails to verify, in case the key was rotated.
+    pub async fn refresh(&self, did: &str) -> Result<String> {
+        self.resolve(did, true).await
+    }
 
-    async fn plc_query(&self, did: &str) -> Result<String> {
-        let url = if let Some(hostname) = did.strip_prefix("did:web:") {
-            format!("https://{}/.well-known/did.json", hostname)
+    async fn resolve(&self, did: &str, refresh: bool) -> Result<String> {
+        self.admit(did, refresh)?;
+
+        let resolved =
+            resolve_verification_method(&self.http_client, &self.plc_hostname, did).await;
+        self.record(did, resolved.is_ok());
+
+        match resolved {
+            Ok(verification_method) => {
+                self.metrics.resolution("ok");
+                verifcation_method_insert(&self.pool, &verification_method).await?;
+                Ok(verification_method.multikey)
+            }
+            Err(err) => {
+                self.metrics.resolution("error");
+                Err(err.context(format!("unable to resolve {}", did)))
+            }
+        }
+    }
+
+    fn admit(&self, did: &str, refresh: bool) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+
+        if let Some((attempted_at, ok)) = state.attempts.get(did) {
+            let elapsed = now.duration_since(*attempted_at);
+            if !ok && elapsed < self.negative_cache_ttl {
+                self.metrics.resolution("negative_cached");
+                return Err(anyhow!("resolution of {} failed recently", did));
+            }
+            if refresh && elapsed < REFRESH_MIN_INTERVAL {
+                return Err(anyhow!("{} was resolved recently", did));
+            }
+        }
+
+        if self.rate_limit == 0 {
+            return Err(anyhow!("on-demand DID resolution is disabled"));
+        }
+
+        let window = if refresh {
+            &mut state.refresh
         } else {
-            format!("https://{}/{}", self.plc_hostname, did)
+            &mut state.unknown
         };
+        if !window.admit(now, self.rate_limit) {
+            self.metrics.resolution("rate_limited");
+            return Err(anyhow!("DID resolution rate limit reached"));
+        }
+
+        Ok(())
+    }
+
+    fn record(&self, did: &str, ok: bool) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let retention = self.negative_cache_ttl.max(REFRESH_MIN_INTERVAL);
+
+        state
+            .attempts
+            .retain(|_, (attempted_at, _)| now.duration_since(*attempted_at) < retention);
+        state.attempts.insert(did.to_string(), (now, ok));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        extract::{Path, State},
+        http::StatusCode,
+        response::IntoResponse,
+        routing::get,
+        Json, Router,
+    };
+    use serde_json::json;
+    use sqlx::SqlitePool;
+
+    use super::*;
+
+    /// A PLC directory serving a DID document for each DID in `keys`.
+    #[derive(Clone, Default)]
+    pub(crate) struct MockPlc {
+        pub(crate) keys: Arc<Mutex<HashMap<String, String>>>,
+        pub(crate) requests: Arc<Mutex<usize>>,
+    }
+
+    async fn resolve_did(State(plc): State<MockPlc>, Path(did): Path<String>) -> impl IntoResponse {
+        *plc.requests.lock().unwrap() += 1;
+        match plc.keys.lock().unwrap().get(&did) {
+            Some(key) => Json(json!({
+                "id": did,
+                "verificationMethod": [{"id": format!("{}#atproto", did), "type": "Multikey", "publicKeyMultibase": key}],
+            }))
+            .into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    /// Serves `plc` on a local port, returning its base URL.
+    pub(crate) async fn serve_plc(plc: MockPlc) -> String {
+        let router = Router::new()
+            .route("/:did", get(resolve_did))
+            .with_state(plc);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{}", address)
+    }
+
+    pub(crate) fn verification_method(did: &str, multikey: &str) -> model::VerificationMethod {
+        model::VerificationMethod {
+            did: did.to_string(),
+            multikey: multikey.to_string(),
+            pds: None,
+            handle: None,
+        }
+    }
+
+    pub(crate) fn did_resolver(pool: StoragePool, plc_url: String, rate_limit: u32) -> DidResolver {
+        DidResolver::new(
+            pool,
+            reqwest::Client::new(),
+            plc_url,
+            rate_limit,
+            std::time::Duration::from_secs(300),
+            VmcMetrics::default(),
+        )
+    }
 
-        let resolved_did: ResolvedPlcDid = self
-            .http_client
-            .get(url)
-            .timeout(Duration::seconds(10).to_std()?)
-            .send()
-            .await?
-            .json()
-            .await?;
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn resolve_on_demand(pool: SqlitePool) -> Result<()> {
+        let pool = StoragePool::from(pool);
+        let plc = MockPlc::default();
+        plc.keys
+            .lock()
+            .unwrap()
+            .insert("did:plc:known".to_string(), "zKey".to_string());
+        let resolver = did_resolver(pool.clone(), serve_plc(plc.clone()).await, 60);
 
-        if resolved_did.id != did {
-            return Err(anyhow!("DID mismatch"));
+        // Resolved once, then served from the cache.
+        assert_eq!(resolver.verification_method("did:plc:known").await?, "zKey");
+        assert_eq!(resolver.verification_method("did:plc:known").await?, "zKey");
+        assert_eq!(*plc.requests.lock().unwrap(), 1);
+        assert_eq!(
+            verification_method_get(&pool, "did:plc:known")
+                .await?
+                .map(|verification_method| verification_method.multikey),
+            Some("zKey".to_string())
+        );
+
+        // Failures are cached too.
+        assert!(resolver
+            .verification_method("did:plc:unknown")
+            .await
+            .is_err());
+        assert!(resolver
+            .verification_method("did:plc:unknown")
+            .await
+            .is_err());
+        assert_eq!(*plc.requests.lock().unwrap(), 2);
+
+        // A refresh replaces the cached key, but only once a minute.
+        verifcation_method_insert(&pool, &verification_method("did:plc:cached", "zOld")).await?;
+        plc.keys
+            .lock()
+            .unwrap()
+            .insert("did:plc:cached".to_string(), "zRotated".to_string());
+        assert_eq!(resolver.refresh("did:plc:cached").await?, "zRotated");
+        assert!(resolver.refresh("did:plc:cached").await.is_err());
+        assert!(resolver.refresh("did:plc:known").await.is_err());
+        assert_eq!(*plc.requests.lock().unwrap(), 3);
+        assert_eq!(
+            verification_method_get(&pool, "did:plc:cached")
+                .await?
+                .map(|verification_method| verification_method.multikey),
+            Some("zRotated".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrations = "migrations/sqlite")]
+    async fn resolve_rate_limited(pool: SqlitePool) -> Result<()> {
+        let pool = StoragePool::from(pool);
+        let plc = MockPlc::default();
+        {
+            let mut keys = plc.keys.lock().unwrap();
+            keys.insert("did:plc:first".to_string(), "zFirst".to_string());
+            keys.insert("did:plc:second".to_string(), "zSecond".to_string());
         }
+        let resolver = did_resolver(pool.clone(), serve_plc(plc.clone()).await, 1);
+
+        assert!(resolver.verification_method("did:plc:first").await.is_ok());
+        assert!(resolver
+            .verification_method("did:plc:second")
+            .await
+            .is_err());
+        assert_eq!(*plc.requests.lock().unwrap(), 1);
+
+        // Refreshes of cached DIDs have their own budget.
+        verifcation_method_insert(&pool, &verification_method("did:plc:second", "zOld")).await?;
+        assert_eq!(resolver.refresh("did:plc:second").await?, "zSecond");
+        assert_eq!(*plc.requests.lock().unwrap(), 2);
 
-        let key = resolved_did
-            .verification_method
-            .first()
-            .map(|value| value.public_key_multibase.clone());
+        let disabled = did_resolver(pool, serve_plc(plc.clone()).await, 0);
+        assert!(disabled.verification_method("did:plc:third").await.is_err());
+        assert!(disabled.refresh("did:plc:second").await.is_err());
+        assert_eq!(*plc.requests.lock().unwrap(), 2);
 
-        key.ok_or(anyhow!("No key found"))
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn did_web_hostnames() {
+        for hostname in [
+            "feeds.example.com",
+            "smokesignal.events",
+            "a-b.example.co.uk",
+        ] {
+            assert!(did_web_hostname(hostname).is_ok(), "{}", hostname);
+        }
+
+        for hostname in [
+            "localhost",
+            "127.0.0.1",
+            "10.0.0.1",
+            "169.254.169.254",
+            "[::1]",
+            "::1",
+            "api.localhost",
+            "printer.local",
+            "metadata.google.internal",
+            "db",
+            "example.com:8080",
+            "example.com%3A8080",
+            "example.com:path",
+            "user@example.com",
+            "Example.com",
+            "127.1",
+            "2130706433",
+            "1.1.1.1.in-addr.arpa",
+            "",
+        ] {
+            assert!(did_web_hostname(hostname).is_err(), "{}", hostname);
+        }
+
+        // Rejected before any request is made.
+        let err = resolve_verification_method(
+            &reqwest::Client::new(),
+            "plc.directory",
+            "did:web:169.254.169.254",
+        )
+        .await
+        .expect_err("IP literals are rejected");
+        assert!(format!("{}", err).contains("is an IP address"));
+    }
+
+    #[test]
+    fn select_atproto_verification_method() -> Result<()> {
+        let document: DidDocument = serde_json::from_value(json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": "did:plc:4acsffvbo4niovge362ptijz",
+            "alsoKnownAs": ["at://smokesignal.events"],
+            "verificationMethod": [
+                {
+                    "id": "did:plc:4acsffvbo4niovge362ptijz#other",
+                    "type": "Multikey",
+                    "controller": "did:plc:4acsffvbo4niovge362ptijz",
+                    "publicKeyMultibase": "zQ3shOther"
+                },
+                {
+                    "id": "did:plc:4acsffvbo4niovge362ptijz#atproto",
+                    "type": "Multikey",
+                    "controller": "did:plc:4acsffvbo4niovge362ptijz",
+                    "publicKeyMultibase": "zQ3shAtproto"
+                }
+            ],
+            "service": [{
+                "id": "#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": "https://pds.example.com"
+            }]
+        }))?;
+
+        assert_eq!(
+            atproto_verification_method("did:plc:4acsffvbo4niovge362ptijz", &document)?,
+            model::VerificationMethod {
+                did: "did:plc:4acsffvbo4niovge362ptijz".to_string(),
+                multikey: "zQ3shAtproto".to_string(),
+                pds: Some("https://pds.example.com".to_string()),
+                handle: Some("smokesignal.events".to_string()),
+            }
+        );
+        assert!(
+            atproto_verification_method("did:plc:cbkjy5n7bk3ax2wplmtjofq2", &document).is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_verification_method() -> Result<()> {
+        use k256::elliptic_curve::sec1::ToEncodedPoint;
+
+        let (_, expected) = crate::crypto::tests::generate_key("ES256K");
+        let (_, key) = multibase::decode(&expected)?;
+        let uncompressed = k256::PublicKey::from_sec1_bytes(&key[2..])?
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec();
+
+        // A did:web document with a relative id and a JWK method alongside.
+        let document: DidDocument = serde_json::from_value(json!({
+            "id": "did:web:feeds.example.com",
+            "verificationMethod": [
+                {
+                    "id": "#jwk",
+                    "type": "JsonWebKey2020",
+                    "publicKeyJwk": {"kty": "EC", "crv": "secp256k1"}
+                },
+                {
+                    "id": "#atproto",
+                    "type": "EcdsaSecp256k1VerificationKey2019",
+                    "publicKeyMultibase": multibase::encode(multibase::Base::Base58Btc, uncompressed)
+                }
+            ]
+        }))?;
+
+        let verification_method =
+            atproto_verification_method("did:web:feeds.example.com", &document)?;
+        assert_eq!(verification_method.multikey, expected);
+        assert_eq!(verification_method.pds, None);
+        assert_eq!(verification_method.handle, None);
+
+        Ok(())
     }
 }